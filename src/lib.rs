@@ -0,0 +1,27 @@
+//! Encrypted peer-to-peer file sharing over libp2p.
+//!
+//! Build a [`Node`] with [`Node::builder`], spawn [`Node::run`], and drive it
+//! through the [`NodeHandle`] returned alongside it:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), p2p_sys::NodeError> {
+//! let (node, handle) = p2p_sys::Node::builder()
+//!     .topic("test-net")
+//!     .secret("password")
+//!     .data_dir("/tmp/p2p_sys")
+//!     .build()?;
+//! tokio::spawn(node.run());
+//! let report = handle.put_file("notes.txt").await?;
+//! println!("{}/{} chunks replicated", report.replicated_chunks, report.total_chunks);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod node;
+
+pub use libp2p::{Multiaddr, PeerId};
+pub use node::command::{
+    Chunking, DownloadInfo, DownloadPhase, ErasureCoding, FileEntry, LogQuery, PeerInfo, PeerScore,
+    PutOptions, PutReport, Redundancy, ReplicationStatus,
+};
+pub use node::{FileTransferLog, Node, NodeBuilder, NodeError, NodeEvent, NodeHandle};
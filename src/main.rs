@@ -1,30 +1,443 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 
-mod node;
+mod repl;
+
+use p2p_sys::node::chunk_store::MemoryChunkStore;
+use p2p_sys::node::config_file::{ConfigFile, CONFIG_FILE};
+use p2p_sys::node::control::{self, ControlRequest, ControlResponse};
+use p2p_sys::{Chunking, DownloadPhase, ErasureCoding, Multiaddr, Node, NodeHandle};
+
+/// The operation failed, e.g. a file was not replicated or not found.
+const EXIT_FAILURE: u8 = 1;
+/// The operation did not finish within `--timeout`.
+const EXIT_TIMEOUT: u8 = 3;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Topic name for the Gossipsub network
-    #[clap(short, long, default_value = "test-net")]
+    #[clap(short, long, default_value = "test-net", global = true)]
     topic: String,
 
     /// Password for authentication
-    #[clap(short, long, default_value = "password")]
+    #[clap(short, long, default_value = "password", global = true)]
     password: String,
+
+    /// Directory holding chunks, manifests, downloads, keys and logs
+    #[clap(long, default_value_os_t = default_data_dir(), global = true)]
+    data_dir: PathBuf,
+
+    /// Key file holding the node identity [default: <data-dir>/keys/identity.key]
+    #[clap(long, global = true)]
+    identity: Option<PathBuf>,
+
+    /// Keep chunks in memory instead of the data directory; they are lost on exit
+    #[clap(long, global = true)]
+    in_memory_chunks: bool,
+
+    /// Serve the HTTP gateway on this address [default when given without a value: 127.0.0.1:8080]
+    #[cfg(feature = "http")]
+    #[clap(long, num_args = 0..=1, default_missing_value = "127.0.0.1:8080", global = true)]
+    http: Option<std::net::SocketAddr>,
+
+    /// Address to listen on, e.g. /ip4/0.0.0.0/tcp/4001; repeatable [default: any port over TCP and QUIC]
+    #[clap(long, global = true)]
+    listen: Vec<Multiaddr>,
+
+    /// Peer to connect to and redial whenever it drops, e.g. /dns4/host/tcp/4001/p2p/<peer id>; repeatable
+    #[clap(long, global = true)]
+    bootstrap: Vec<Multiaddr>,
+
+    /// TOML file with `listen` and `peers` address lists [default: <data-dir>/config.toml if it exists]
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Do not discover peers on the local network with mDNS
+    #[clap(long, global = true)]
+    no_mdns: bool,
+
+    /// Peers keeping a copy of each chunk of files uploaded from this node
+    #[clap(long, default_value_t = 3, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+    replication_factor: u16,
+
+    /// How uploaded files are split: fixed, cdc, or cdc:<min>:<avg>:<max> in bytes
+    /// [default: fixed, or the daemon's setting for one-shot uploads]
+    #[clap(long, global = true)]
+    chunking: Option<Chunking>,
+
+    /// Relay connections for peers behind NAT; run it on a publicly reachable host
+    #[clap(long, global = true)]
+    relay_server: bool,
+
+    /// Seconds a one-shot command run without a daemon waits for peers before it starts
+    #[clap(long, default_value_t = 10, global = true)]
+    discovery_wait: u64,
+
+    /// Seconds a download may take before it fails
+    #[clap(long, default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    download_deadline: u64,
+
+    /// Seconds a one-shot command may take before giving up
+    #[clap(long, default_value_t = 120, global = true)]
+    timeout: u64,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a node without the interactive prompt, controlled over its socket
+    Daemon,
+    /// Upload files, then exit once every replica has answered
+    #[clap(alias = "upload")]
+    Put {
+        /// Files, directories or glob patterns to upload
+        #[clap(required = true)]
+        paths: Vec<String>,
+        /// Peers keeping a copy of each chunk [default: --replication-factor]
+        #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+        replicas: Option<u16>,
+        /// Erasure code each file into <data>+<parity> shards per stripe, e.g. 4+2,
+        /// instead of replicating its chunks
+        #[clap(long, value_name = "DATA+PARITY", conflicts_with = "replicas")]
+        erasure: Option<ErasureCoding>,
+    },
+    /// Download a file from the network, then exit
+    Get {
+        /// Name of the file to download
+        name: String,
+        /// Where to write the file [default: <data-dir>/downloads/<name>]
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List downloads in progress, including ones resumed after a restart, and recently failed ones
+    Downloads {
+        /// Cancel the download of this file, or forget its failure, instead
+        #[clap(long, value_name = "NAME")]
+        cancel: Option<String>,
+    },
+    /// Change how many peers keep a copy of each chunk of a file uploaded from this node
+    Replicate {
+        /// Name of the uploaded file
+        name: String,
+        #[clap(value_parser = clap::value_parser!(u16).range(1..))]
+        replicas: u16,
+    },
+    /// List files this node holds a manifest for
+    List,
+    /// List authenticated peers and their scores
+    Peers,
+    /// Print the file transfer logs as JSON lines
+    Logs,
+}
+
+fn default_data_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("p2p_sys")
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     let _ = tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .try_init();
 
     let args = Args::parse();
 
+    match run(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<ExitCode, Box<dyn Error>> {
+    let socket_path = control::socket_path(&args.data_dir);
+
+    let Some(command) = args.command.as_ref().filter(|c| !matches!(c, Command::Daemon)) else {
+        // Interactive peer or daemon: a long-lived node serving the control socket.
+        let interactive = args.command.is_none();
+        println!("Starting peer-to-peer system with topic: {}", args.topic);
+        let listener = control::bind(&socket_path).await?;
+        #[cfg(feature = "http")]
+        let http = args.http;
+        let (node, handle) = build_node(args)?;
+        #[cfg(feature = "http")]
+        if let Some(addr) = http {
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                if let Err(e) = p2p_sys::node::http_gateway::serve(addr, handle).await {
+                    println!("HTTP gateway stopped: {}", e);
+                }
+            });
+        }
+        let control_handle = handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(listener, control_handle).await {
+                println!("Control socket stopped: {}", e);
+            }
+        });
+        if interactive {
+            let node = node.run();
+            tokio::pin!(node);
+            let exit = tokio::select! {
+                result = &mut node => {
+                    result?;
+                    return Ok(ExitCode::SUCCESS);
+                }
+                exit = repl::run(handle) => exit,
+            };
+            // Without stdin, e.g. when started in the background, the peer
+            // keeps serving until it is interrupted.
+            if exit == repl::Exit::StdinClosed {
+                println!("Stdin closed; the peer keeps running until Ctrl-C.");
+                tokio::select! {
+                    result = &mut node => result?,
+                    result = tokio::signal::ctrl_c() => result?,
+                }
+            }
+        } else {
+            drop(handle);
+            node.run().await?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    };
+    let request = control_request(command, args.chunking)?;
+
+    // Prefer a daemon already running on this data directory.
+    if let Ok(mut stream) = control::connect(&socket_path).await {
+        let response = tokio::time::timeout(
+            Duration::from_secs(args.timeout),
+            control::request(&mut stream, &request),
+        )
+        .await;
+        return match response {
+            Ok(response) => Ok(print_response(response?)),
+            Err(_) => Ok(timed_out(args.timeout)),
+        };
+    }
+
+    // Otherwise run a temporary node for as long as the command takes.
     println!("Starting peer-to-peer system with topic: {}", args.topic);
+    let timeout = Duration::from_secs(args.timeout);
+    let discovery_wait = Duration::from_secs(args.discovery_wait);
+    let (node, handle) = build_node(args)?;
+    let client = tokio::time::timeout(timeout, async {
+        if !matches!(request, ControlRequest::Logs { .. } | ControlRequest::List) {
+            tokio::time::sleep(discovery_wait).await;
+        }
+        control::execute(request, &handle).await
+    });
+    tokio::select! {
+        result = node.run() => {
+            result?;
+            Err("node stopped before the command finished".into())
+        }
+        result = client => match result {
+            Ok(response) => Ok(print_response(response)),
+            Err(_) => Ok(timed_out(timeout.as_secs())),
+        },
+    }
+}
+
+fn build_node(args: Args) -> Result<(Node, NodeHandle), p2p_sys::NodeError> {
+    let config_path = args.config.or_else(|| {
+        let path = args.data_dir.join(CONFIG_FILE);
+        path.exists().then_some(path)
+    });
+    let config = match config_path {
+        Some(path) => ConfigFile::load(&path)?,
+        None => ConfigFile::default(),
+    };
+
+    let mut builder = Node::builder()
+        .topic(args.topic)
+        .secret(args.password)
+        .data_dir(args.data_dir)
+        .mdns(!args.no_mdns)
+        .relay_server(args.relay_server)
+        .replication_factor(usize::from(args.replication_factor))
+        .chunking(args.chunking.unwrap_or_default())
+        .download_deadline(Duration::from_secs(args.download_deadline));
+    if let Some(identity) = args.identity {
+        builder = builder.identity_path(identity);
+    }
+    if args.in_memory_chunks {
+        builder = builder.chunk_store(Arc::new(MemoryChunkStore::default()));
+    }
+    for addr in config.listen.into_iter().chain(args.listen) {
+        builder = builder.listen_on(addr);
+    }
+    for addr in config.peers.into_iter().chain(args.bootstrap) {
+        builder = builder.bootstrap_peer(addr);
+    }
+    builder.build()
+}
+
+fn control_request(
+    command: &Command,
+    chunking: Option<Chunking>,
+) -> Result<ControlRequest, Box<dyn Error>> {
+    // Paths are resolved here because the node may run in another working directory.
+    Ok(match command {
+        Command::Daemon => unreachable!("the daemon is not a one-shot command"),
+        Command::Put {
+            paths,
+            replicas,
+            erasure,
+        } => ControlRequest::Upload {
+            paths: paths
+                .iter()
+                .map(|path| Ok(std::path::absolute(path)?.to_string_lossy().into_owned()))
+                .collect::<Result<_, std::io::Error>>()?,
+            replicas: replicas.map(usize::from),
+            erasure: *erasure,
+            chunking,
+        },
+        Command::Replicate { name, replicas } => ControlRequest::Replicate {
+            file_name: name.clone(),
+            replicas: usize::from(*replicas),
+        },
+        Command::Get { name, output } => ControlRequest::Download {
+            file_name: name.clone(),
+            output: output.as_deref().map(std::path::absolute).transpose()?,
+        },
+        Command::Downloads { cancel: None } => ControlRequest::Downloads,
+        Command::Downloads {
+            cancel: Some(name),
+        } => ControlRequest::CancelDownload {
+            file_name: name.clone(),
+        },
+        Command::List => ControlRequest::List,
+        Command::Peers => ControlRequest::Peers,
+        Command::Logs => ControlRequest::Logs {
+            query: Default::default(),
+        },
+    })
+}
+
+/// Prints the result of a one-shot command and picks the exit code.
+fn print_response(response: ControlResponse) -> ExitCode {
+    match response {
+        ControlResponse::Uploaded { files } => {
+            let mut complete = true;
+            for report in &files {
+                println!(
+                    "{}: {}/{} chunks replicated",
+                    report.file_name, report.replicated_chunks, report.total_chunks
+                );
+                complete &= report.is_complete();
+            }
+            exit_code(complete)
+        }
+        ControlResponse::Downloaded { path } => {
+            println!("{}", path.display());
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Downloads { downloads } => {
+            for download in downloads {
+                let total = match download.total_chunks {
+                    Some(total) => total.to_string(),
+                    None => "?".to_string(),
+                };
+                let state = match &download.state {
+                    DownloadPhase::Downloading => format!("{} in flight", download.in_flight),
+                    DownloadPhase::Failed { reason, .. } => format!("failed: {}", reason),
+                };
+                println!(
+                    "{}\t{}/{} chunks\t{}\tstarted {}",
+                    download.file_name,
+                    download.received_chunks,
+                    total,
+                    state,
+                    download.started_at.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Cancelled { file_name } => {
+            println!("{}: download cancelled", file_name);
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Replicated { file_name, replicas } => {
+            println!("{}: keeping {} replica(s) of each chunk", file_name, replicas);
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Files { files } => {
+            for file in files {
+                let replication = match file.replication {
+                    Some(status) => format!(
+                        "\t{}/{} replicas",
+                        status.live_replicas, status.replication_factor
+                    ),
+                    None => String::new(),
+                };
+                println!(
+                    "{}\t{} bytes\t{}/{} chunks local{}",
+                    file.file_name,
+                    file.total_length,
+                    file.local_chunks,
+                    file.total_chunks,
+                    replication
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Peers { peers } => {
+            for peer in peers {
+                let score = match peer.score {
+                    Some(score) => format!("{:.2}", score),
+                    None => "-".to_string(),
+                };
+                if peer.connected && !peer.direct {
+                    println!("{}\t{}\trelayed", peer.peer_id, score);
+                } else {
+                    println!("{}\t{}", peer.peer_id, score);
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Scores { scores } => {
+            for peer in scores {
+                println!("{}\t{:.2}", peer.peer_id, peer.score);
+            }
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Logs { logs } => {
+            for log in logs {
+                match serde_json::to_string(&log) {
+                    Ok(line) => println!("{}", line),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        ControlResponse::Error { message } => {
+            eprintln!("Error: {}", message);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+fn timed_out(seconds: u64) -> ExitCode {
+    eprintln!("Timed out after {} seconds", seconds);
+    ExitCode::from(EXIT_TIMEOUT)
+}
 
-    node::run_peer_to_peer_system(args.topic, args.password).await
+fn exit_code(success: bool) -> ExitCode {
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FAILURE)
+    }
 }
@@ -1,105 +1,307 @@
 use futures::stream::StreamExt;
 use libp2p::{
-    gossipsub, mdns,
-    swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux, PeerId,
+    autonat, dcutr, gossipsub, identify, kad, mdns, relay,
+    request_response::{self, OutboundRequestId, ProtocolSupport},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
+    tcp, yamux, Multiaddr, PeerId,
 };
-use std::error::Error;
 use std::hash::{Hash, Hasher};
-use std::io::Read;
-use std::io::Write;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
 use rand::prelude::IteratorRandom;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use tokio::{io, io::AsyncBufReadExt, select};
+use tokio::select;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use rand::Rng;
+mod auth;
+pub mod chunk_store;
 mod chunker;
+pub mod command;
+pub mod config_file;
+pub mod control;
+mod crypto;
+mod discovery;
+mod download_journal;
+mod erasure;
+mod error;
+mod event;
 mod file_operations;
+mod handle;
+#[cfg(feature = "http")]
+pub mod http_gateway;
+mod identity;
+mod manifest;
+mod protocol;
+mod reachability;
+mod replication;
+mod scheduler;
+mod static_peers;
 mod storage_manager;
+mod upload_pipeline;
+mod wire;
+
+pub use error::NodeError;
+pub use event::NodeEvent;
+pub use handle::{Node, NodeBuilder, NodeHandle};
+
+use auth::{AuthChallenge, AuthCodec, AuthProof, AuthProtocol};
+use chunk_store::ChunkStore;
+use command::{
+    Chunking, DownloadInfo, DownloadPhase, FileEntry, NodeCommand, PeerInfo, PeerScore, PutOptions, PutReport, Redundancy,
+    ReplicationStatus,
+};
+use crypto::TopicKeys;
+use discovery::ProviderQuery;
+use download_journal::DownloadJournal;
+use file_operations::ReassemblyWriter;
+use manifest::{merkle_root, FileManifest, ShardBitmap};
+use protocol::{FileRequest, FileResponse, FileTransferCodec, FileTransferProtocol};
+use reachability::Reachability;
+use replication::ReplicationManager;
+use scheduler::DownloadScheduler;
+use static_peers::StaticPeers;
+use storage_manager::StorageLayout;
+use upload_pipeline::{ChunkedFile, PipelineEvent};
+use wire::{WireError, WireMessage};
+
+/// Everything a node needs to start, resolved by `NodeBuilder::build`.
+struct NodeConfig {
+    topic: String,
+    keys: TopicKeys,
+    keypair: libp2p::identity::Keypair,
+    layout: StorageLayout,
+    chunk_store: Arc<dyn ChunkStore>,
+    listen_addrs: Vec<Multiaddr>,
+    bootstrap_peers: Vec<Multiaddr>,
+    mdns: bool,
+    relay_server: bool,
+    replication_factor: usize,
+    chunking: Chunking,
+    download_deadline: Duration,
+}
 
 type SharedFileTransferLogs = Arc<Mutex<Vec<FileTransferLog>>>;
 
+/// How long a failed download stays listed before it is garbage collected.
+const FAILED_DOWNLOAD_RETENTION: Duration = Duration::from_secs(60 * 60);
+
 struct DownloadState {
-    total_chunks: usize,
-    received_chunks: usize,
-    chunks_data: HashMap<usize, Vec<u8>>, // chunk_index -> data
+    manifest: Option<FileManifest>,
+    deadline: Instant,
+    scheduler: DownloadScheduler,
+    located: HashSet<String>,   // chunk hashes with a provider lookup started
+    exhausted: HashSet<String>, // shards no peer could be found to serve
+    assembling: bool,           // every chunk is in and the file is being written
 }
 
 type SharedDownloads = Arc<Mutex<HashMap<String, DownloadState>>>;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-struct FileTransferLog {
-    peer_id: String,
+/// What an outbound request-response request was sent for.
+enum PendingRequest {
+    Store { file_name: String, chunk_hash: String },
+    Fetch { file_name: String, chunk_hash: String },
+    Manifest { file_name: String },
+    /// A replica was asked which of these chunks it still holds.
+    Audit { chunk_hashes: Vec<String> },
+}
+
+/// A chunk or parity shard of an upload, or a chunk being re-replicated,
+/// waiting for a free store request. Its bytes are read back from the chunk
+/// store when it is sent.
+struct QueuedStore {
+    job: Option<u64>, // none for re-replication
+    file_name: String,
+    label: String,
+    chunk_hash: String,
+    target_peers: Vec<PeerId>,
+}
+
+/// A download that gave up, listed until it is garbage collected.
+struct FailedDownload {
+    info: DownloadInfo,
+    failed_at: Instant,
+}
+
+/// A `Put` command waiting for its files to be chunked and its store
+/// requests to be answered.
+struct PutJob {
+    id: u64,
+    redundancy: Redundancy,
+    files: Vec<(String, Vec<String>)>, // file name -> chunk hashes
+    uploading: usize, // files the pipeline has not finished yet
+    outstanding: HashSet<OutboundRequestId>,
+    stored: HashSet<String>, // chunk hashes a replica confirmed
+    /// Stores confirmed while a file is still being chunked, recorded with
+    /// the replication manager once the file is tracked.
+    confirmed: Vec<(String, PeerId)>,
+    reply: oneshot::Sender<Result<Vec<PutReport>, NodeError>>,
+}
+
+impl PutJob {
+    fn finish(self) {
+        let reports = self
+            .files
+            .iter()
+            .map(|(file_name, chunk_hashes)| PutReport {
+                file_name: file_name.clone(),
+                total_chunks: chunk_hashes.len(),
+                replicated_chunks: chunk_hashes
+                    .iter()
+                    .filter(|hash| self.stored.contains(*hash))
+                    .count(),
+            })
+            .collect();
+        let _ = self.reply.send(Ok(reports));
+    }
+}
+
+/// A `Get` command waiting for its download to finish.
+struct GetWaiter {
+    output: Option<PathBuf>,
+    reply: oneshot::Sender<Result<PathBuf, NodeError>>,
+}
+
+/// Where a download stands after a chunk arrives.
+enum DownloadStatus {
+    InProgress,
+    /// Every chunk is in or can be rebuilt from the received shards; the
+    /// file is ready to be reassembled.
+    Assemble(Box<FileManifest>, HashSet<String>),
+    /// Reassembly found these chunks corrupt in the chunk store and deleted
+    /// them; they have to be fetched again.
+    Refetch(Vec<String>),
+    Complete(PathBuf),
+    Discarded(String),
+}
+
+/// The outcome of reassembling a download, sent back from its blocking task.
+struct Assembled {
     file_name: String,
-    timestamp: DateTime<Utc>,
-    password: String,
+    result: Result<DownloadStatus, NodeError>,
+}
+
+/// State shared by the command and swarm event handlers.
+struct NodeState {
+    local_peer_id: PeerId,
+    keypair: libp2p::identity::Keypair,
+    topic: gossipsub::IdentTopic,
+    keys: TopicKeys,
+    layout: StorageLayout,
+    chunk_store: Arc<dyn ChunkStore>,
+    peer_scores: HashMap<PeerId, f64>,
+    file_transfer_logs: SharedFileTransferLogs,
+    downloads: SharedDownloads,
+    pending_requests: HashMap<OutboundRequestId, PendingRequest>,
+    authenticated_peers: HashSet<PeerId>,
+    pending_challenges: HashMap<OutboundRequestId, (PeerId, Vec<u8>)>, // request -> (peer, nonce)
+    put_jobs: Vec<PutJob>,
+    next_put_job: u64,
+    store_queue: VecDeque<QueuedStore>,
+    pipeline_events: mpsc::Sender<PipelineEvent>,
+    assembled: mpsc::UnboundedSender<Assembled>,
+    get_waiters: HashMap<String, Vec<GetWaiter>>, // file name -> waiting commands
+    events: broadcast::Sender<NodeEvent>,
+    provider_queries: HashMap<kad::QueryId, ProviderQuery>,
+    provided_keys: HashSet<kad::RecordKey>,
+    deferred_manifest_fetches: HashMap<PeerId, Vec<String>>, // provider -> file names
+    audit_cursors: HashMap<PeerId, usize>, // holder -> first chunk of its next audit batch
+    static_peers: StaticPeers,
+    dht_bootstrapped: bool, // bootstrapped through the first static peer to connect
+    reachability: Reachability,
+    replication: ReplicationManager,
+    replication_factor: usize, // for uploads that do not ask for a count
+    chunking: Chunking,        // for uploads that do not ask for a scheme
+    download_journal: DownloadJournal,
+    /// Journaled downloads waiting for the first authenticated peer to resume.
+    unresumed_downloads: Vec<String>,
+    download_deadline: Duration,
+    failed_downloads: HashMap<String, FailedDownload>,
+}
+
+impl NodeState {
+    fn emit(&self, event: NodeEvent) {
+        // Sending only fails while nobody is subscribed.
+        let _ = self.events.send(event);
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileTransferLog {
+    pub peer_id: String,
+    pub file_name: String,
+    pub chunk_hash: String,
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(NetworkBehaviour)]
 pub struct MyBehaviour {
     pub gossipsub: gossipsub::Behaviour,
-    pub mdns: mdns::tokio::Behaviour,
+    /// Local network discovery; off when multicast is unavailable.
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    pub identify: identify::Behaviour,
+    pub relay_client: relay::client::Behaviour,
+    /// Relays circuits for peers behind NAT; on with `--relay-server`.
+    pub relay_server: Toggle<relay::Behaviour>,
+    pub dcutr: dcutr::Behaviour,
+    pub autonat: autonat::Behaviour,
+    pub file_transfer: request_response::Behaviour<FileTransferCodec>,
+    pub auth: request_response::Behaviour<AuthCodec>,
 }
 
 fn search_transfer_log(
-    logs: &Vec<FileTransferLog>,
+    logs: &[FileTransferLog],
     peer_id: Option<&str>,
     file_name: Option<&str>,
     date: Option<DateTime<Utc>>,
 ) -> Vec<FileTransferLog> {
     logs.iter()
         .filter(|log| {
-            peer_id.map_or(true, |id| log.peer_id == id)
-                && file_name.map_or(true, |name| log.file_name.contains(name))
-                && date.map_or(true, |d| log.timestamp.date_naive() == d.date_naive())
+            peer_id.is_none_or(|id| log.peer_id == id)
+                && file_name.is_none_or(|name| log.file_name.contains(name))
+                && date.is_none_or(|d| log.timestamp.date_naive() == d.date_naive())
         })
         .cloned()
         .collect()
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct FileRequestMessage {
-    sender: String,
-    password: String,
-    file_name: String,
-}
-
-#[derive(serde::Serialize, serde::Deserialize)]
-struct FileMetadataMessage {
-    sender: String,
-    password: String,
-    file_name: String,
-    total_chunks: usize,
-}
-
-#[derive(serde::Serialize, serde::Deserialize)]
-struct FileMessage {
-    sender: String,
-    password: String,
-    file_name: String,
-    data: Vec<u8>,
-    receivers: Vec<String>,
-}
-
-pub async fn run_peer_to_peer_system(
-    topic_name: String,
-    password: String,
-) -> Result<(), Box<dyn Error>> {
-    let mut peer_scores: HashMap<PeerId, f64> = HashMap::new();
-    let file_transfer_logs: SharedFileTransferLogs = Arc::new(Mutex::new(Vec::new()));
-    let downloads: SharedDownloads = Arc::new(Mutex::new(HashMap::new()));
-
-    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+/// Runs a node until every handle sending it `commands` is dropped.
+async fn run_node(
+    config: NodeConfig,
+    mut commands: mpsc::Receiver<NodeCommand>,
+    events: broadcast::Sender<NodeEvent>,
+) -> Result<(), NodeError> {
+    let NodeConfig {
+        topic: topic_name,
+        keys,
+        keypair,
+        layout,
+        chunk_store,
+        listen_addrs,
+        bootstrap_peers,
+        mdns: enable_mdns,
+        relay_server: enable_relay_server,
+        replication_factor,
+        chunking,
+        download_deadline,
+    } = config;
+
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone())
         .with_tokio()
         .with_tcp(tcp::Config::default(), libp2p::noise::Config::new, || {
             yamux::Config::default()
-        })?
+        })
+        .map_err(|e| NodeError::Network(e.to_string()))?
         .with_quic()
-        .with_behaviour(|key| {
+        .with_dns()?
+        .with_relay_client(libp2p::noise::Config::new, yamux::Config::default)
+        .map_err(|e| NodeError::Network(e.to_string()))?
+        .with_behaviour(|key, relay_client| {
             let local_peer_id = key.public().to_peer_id();
 
             let message_id_fn = |message: &gossipsub::Message| {
@@ -113,16 +315,49 @@ pub async fn run_peer_to_peer_system(
                 .validation_mode(gossipsub::ValidationMode::Strict)
                 .message_id_fn(message_id_fn)
                 .build()
-                .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
+                .map_err(io::Error::other)?;
 
             let gossipsub = gossipsub::Behaviour::new(
                 gossipsub::MessageAuthenticity::Signed(key.clone()),
                 gossipsub_config,
             )?;
 
-            let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id.clone())?;
-            Ok(MyBehaviour { gossipsub, mdns })
-        })?
+            let mdns = if enable_mdns {
+                Some(mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id)?)
+            } else {
+                None
+            };
+            let kademlia = discovery::new_kademlia(local_peer_id);
+            let identify = identify::Behaviour::new(identify::Config::new(
+                discovery::IDENTIFY_PROTOCOL.to_string(),
+                key.public(),
+            ));
+            let relay_server = enable_relay_server.then(|| reachability::new_relay_server(local_peer_id));
+
+            let file_transfer = request_response::Behaviour::with_codec(
+                FileTransferCodec,
+                [(FileTransferProtocol(), ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+            );
+            let auth = request_response::Behaviour::with_codec(
+                AuthCodec,
+                [(AuthProtocol(), ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(Duration::from_secs(10)),
+            );
+            Ok(MyBehaviour {
+                gossipsub,
+                mdns: mdns.into(),
+                kademlia,
+                identify,
+                relay_client,
+                relay_server: relay_server.into(),
+                dcutr: dcutr::Behaviour::new(local_peer_id),
+                autonat: reachability::new_autonat(local_peer_id),
+                file_transfer,
+                auth,
+            })
+        })
+        .map_err(|e| NodeError::Network(e.to_string()))?
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
         .build();
 
@@ -131,38 +366,157 @@ pub async fn run_peer_to_peer_system(
 
     let local_peer_id = *swarm.local_peer_id();
     println!("Local Peer ID: {}", local_peer_id);
-
-    let mut stdin = io::BufReader::new(io::stdin()).lines();
-
-    swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    println!("Data directory: {}", layout.root().display());
+    print_chunk_store_usage(chunk_store.as_ref());
+
+    let transfer_logs = storage_manager::load_transfer_logs(&layout)?;
+    let replication = ReplicationManager::load(&layout)?;
+    let download_journal = DownloadJournal::load(&layout)?;
+    let unresumed_downloads = download_journal.file_names();
+    let (pipeline_events, mut pipeline_rx) = mpsc::channel(upload_pipeline::PIPELINE_QUEUE);
+    let (assembled, mut assembled_rx) = mpsc::unbounded_channel();
+    let mut state = NodeState {
+        local_peer_id,
+        keypair,
+        topic,
+        keys,
+        layout,
+        chunk_store,
+        peer_scores: HashMap::new(),
+        file_transfer_logs: Arc::new(Mutex::new(transfer_logs)),
+        downloads: Arc::new(Mutex::new(HashMap::new())),
+        pending_requests: HashMap::new(),
+        authenticated_peers: HashSet::new(),
+        pending_challenges: HashMap::new(),
+        put_jobs: Vec::new(),
+        next_put_job: 0,
+        store_queue: VecDeque::new(),
+        pipeline_events,
+        assembled,
+        get_waiters: HashMap::new(),
+        events,
+        provider_queries: HashMap::new(),
+        provided_keys: HashSet::new(),
+        deferred_manifest_fetches: HashMap::new(),
+        audit_cursors: HashMap::new(),
+        static_peers: StaticPeers::new(bootstrap_peers),
+        dht_bootstrapped: false,
+        reachability: Reachability::default(),
+        replication,
+        replication_factor,
+        chunking,
+        download_journal,
+        unresumed_downloads,
+        download_deadline,
+        failed_downloads: HashMap::new(),
+    };
+    announce_local_content(&mut swarm, &mut state);
+
+    for addr in listen_addrs {
+        swarm.listen_on(addr)?;
+    }
 
     println!(
         "Listening for peers and publishing to topic: {}",
         topic_name
     );
 
+    let mut redial_timer = tokio::time::interval(Duration::from_secs(1));
+    let mut audit_timer = tokio::time::interval(replication::AUDIT_INTERVAL);
+    let mut fetch_timer = tokio::time::interval(Duration::from_secs(1));
+    let mut bootstrap_timer = tokio::time::interval(discovery::BOOTSTRAP_INTERVAL);
+    let mut score_timer = tokio::time::interval(Duration::from_secs(60));
+
     loop {
         select! {
-            line = stdin.next_line() => {
-                match line {
-                    Ok(Some(input)) => {
-                        handle_user_input(&input, &mut swarm, &topic, &local_peer_id, &password, &mut peer_scores, Arc::clone(&file_transfer_logs), Arc::clone(&downloads)).await?;
-                    },
-                    Ok(None) => break Ok(()),
-                    Err(e) => {
-                        println!("Error reading stdin: {}", e);
-                    }
+            command = commands.recv() => {
+                match command {
+                    Some(command) => handle_command(command, &mut swarm, &mut state),
+                    None => break Ok(()),
                 }
             }
 
             event = swarm.select_next_some() => {
-                handle_swarm_event(event, &mut swarm, &password, &mut peer_scores, &local_peer_id, &topic, Arc::clone(&file_transfer_logs), Arc::clone(&downloads)).await?;
+                // A failure handling one event must not stop the node.
+                if let Err(e) = handle_swarm_event(event, &mut swarm, &mut state).await {
+                    println!("Failed to handle network event: {}", e);
+                }
+            }
+            Some(assembled) = assembled_rx.recv() => {
+                finish_assembly(assembled, &mut swarm, &mut state);
+            }
+            Some(event) = pipeline_rx.recv(), if state.store_queue.len() < upload_pipeline::MAX_QUEUED_STORES => {
+                handle_pipeline_event(event, &mut swarm, &mut state);
+            }
+            _ = redial_timer.tick() => {
+                state.static_peers.dial_due(&mut swarm);
+            }
+            _ = audit_timer.tick() => {
+                audit_replication(&mut swarm, &mut state);
+                state.replication.flush();
+            }
+            _ = fetch_timer.tick() => {
+                drive_downloads(&mut swarm, &mut state);
+            }
+            _ = bootstrap_timer.tick() => {
+                discovery::bootstrap(&mut swarm.behaviour_mut().kademlia);
+            }
+            _ = score_timer.tick() => {
+                remove_disconnected_peers_and_decay_scores(&swarm, &mut state.peer_scores);
+            }
+        }
+    }
+}
+
+/// Announces every manifest and chunk held locally in the DHT, since
+/// provider records do not survive a restart.
+fn announce_local_content(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    match state.chunk_store.list() {
+        Ok(hashes) => {
+            for hash in hashes {
+                provide(swarm, state, discovery::chunk_key(&state.keys, &hash));
             }
-            _ = tokio::time::sleep(Duration::from_secs(60)) => {
-                remove_disconnected_peers_and_decay_scores(&swarm, &mut peer_scores);
+        }
+        Err(e) => println!("Could not list chunk store: {}", e),
+    }
+    match storage_manager::list_manifests(&state.layout) {
+        Ok(manifests) => {
+            for manifest in manifests {
+                if manifest
+                    .shard_hashes()
+                    .iter()
+                    .any(|hash| state.chunk_store.has(hash))
+                {
+                    provide(swarm, state, discovery::manifest_key(&state.keys, &manifest.file_name));
+                }
             }
         }
+        Err(e) => println!("Could not list manifests: {}", e),
+    }
+}
+
+/// Announces this node as a provider of `key` unless it already did.
+fn provide(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState, key: kad::RecordKey) {
+    if state.provided_keys.insert(key.clone()) {
+        discovery::provide(&mut swarm.behaviour_mut().kademlia, key);
+    }
+}
+
+fn print_chunk_store_usage(store: &dyn ChunkStore) {
+    match store.list() {
+        Ok(hashes) => {
+            let total_bytes: usize = hashes
+                .iter()
+                .filter_map(|hash| store.stat(hash).ok())
+                .map(|chunk| chunk.size)
+                .sum();
+            println!(
+                "Chunk store holds {} chunk(s), {} bytes",
+                hashes.len(),
+                total_bytes
+            );
+        }
+        Err(e) => println!("Could not list chunk store: {}", e),
     }
 }
 
@@ -174,7 +528,7 @@ fn remove_disconnected_peers_and_decay_scores(
 
     for peer_id in peer_scores.keys() {
         if !swarm.is_connected(peer_id) {
-            disconnected_peers.push(peer_id.clone());
+            disconnected_peers.push(*peer_id);
         }
     }
 
@@ -193,19 +547,38 @@ fn remove_disconnected_peers_and_decay_scores(
 }
 
 fn update_peer_score(peer_scores: &mut HashMap<PeerId, f64>, peer_id: &PeerId, delta: f64) {
-    let score = peer_scores.entry(peer_id.clone()).or_insert(0.0);
+    let score = peer_scores.entry(*peer_id).or_insert(0.0);
     *score += delta;
     println!("Updated score for {}: {}", peer_id, *score);
 }
 
-fn select_peers(peer_scores: &HashMap<PeerId, f64>, n: usize) -> Vec<PeerId> {
+/// Picks up to `n` peers to store a chunk. Directly connected peers come
+/// first; peers reached only through a relay fill the remaining places.
+fn select_peers(
+    peer_scores: &HashMap<PeerId, f64>,
+    reachability: &Reachability,
+    n: usize,
+) -> Vec<PeerId> {
+    let (direct, relayed): (HashMap<PeerId, f64>, HashMap<PeerId, f64>) = peer_scores
+        .iter()
+        .map(|(peer_id, score)| (*peer_id, *score))
+        .partition(|(peer_id, _)| reachability.is_direct(peer_id));
+
+    let mut selected_peers = rank_peers(&direct, n);
+    selected_peers.extend(rank_peers(&relayed, n - selected_peers.len()));
+    selected_peers
+}
+
+/// The `n - 1` best scored peers plus one random other peer, so new peers
+/// get a chance to earn a score.
+fn rank_peers(peer_scores: &HashMap<PeerId, f64>, n: usize) -> Vec<PeerId> {
     let mut peers_by_score: Vec<_> = peer_scores.iter().collect();
     peers_by_score.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
 
     let mut selected_peers: Vec<PeerId> = peers_by_score
         .iter()
         .take(n.saturating_sub(1))
-        .map(|(peer_id, _)| (*peer_id).clone())
+        .map(|(peer_id, _)| **peer_id)
         .collect();
 
     let remaining_peers: Vec<_> = peer_scores
@@ -222,542 +595,2416 @@ fn select_peers(peer_scores: &HashMap<PeerId, f64>, n: usize) -> Vec<PeerId> {
     selected_peers
 }
 
-async fn handle_user_input(
-    input: &str,
+/// Encodes `message` and publishes it to the topic encrypted with the topic key.
+fn publish_gossip(
     swarm: &mut libp2p::Swarm<MyBehaviour>,
     topic: &gossipsub::IdentTopic,
-    local_peer_id: &PeerId,
-    password: &str,
-    peer_scores: &mut HashMap<PeerId, f64>,
-    file_transfer_logs: SharedFileTransferLogs,
-    downloads: SharedDownloads,
-) -> Result<(), Box<dyn Error>> {
-    let trimmed = input.trim();
-    if trimmed == "@upload" {
-        if let Some(file_path) = file_operations::select_file() {
-            let file_name = file_path
-                .file_name()
-                .and_then(|name| name.to_str())
-                .unwrap_or("unknown")
-                .to_string();
-
-            let chunks_metadata = chunker::chunk_file(file_path.to_str().unwrap())?;
-            let total_chunks = chunks_metadata.len();
-            if total_chunks == 0 {
-                println!("No chunks created, file might be empty!");
-                return Ok(());
-            }
-
-            let local_peer_dir = format!("./{}", local_peer_id);
-            std::fs::create_dir_all(&local_peer_dir)?;
-
-            let mut file = std::fs::File::open(&file_path)?;
-
-            for (i, metadata) in chunks_metadata.iter().enumerate() {
-                let mut buffer = vec![0; metadata.size];
-                file.read_exact(&mut buffer)?;
-
-                let chunk_filename = format!("{}({}-of-{})", file_name, i + 1, total_chunks);
-                storage_manager::save_chunk(&buffer, &metadata, &local_peer_dir, &chunk_filename)?;
-                let target_peers = select_peers(peer_scores, 3);
+    keys: &TopicKeys,
+    message: WireMessage,
+) -> Result<(), NodeError> {
+    let serialized = wire::encode(message)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), keys.seal_message(&serialized))?;
+    Ok(())
+}
 
-                if target_peers.is_empty() {
-                    println!("No valid peers to send the file.");
-                    return Ok(());
+fn handle_command(
+    command: NodeCommand,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) {
+    match command {
+        NodeCommand::Put {
+            paths,
+            options,
+            reply,
+        } => {
+            let PutOptions {
+                redundancy,
+                chunking,
+            } = options;
+            let redundancy =
+                redundancy.unwrap_or(Redundancy::Replication(state.replication_factor));
+            let chunking = chunking.unwrap_or(state.chunking);
+            if let Err(e) = check_redundancy(redundancy).and_then(|_| check_chunking(chunking)) {
+                let _ = reply.send(Err(e));
+                return;
+            }
+            let mut files = Vec::new();
+            for spec in &paths {
+                match resolve_upload_paths(spec) {
+                    Ok(more) => files.extend(more),
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        return;
+                    }
                 }
-                let receivers: Vec<String> = target_peers.iter().map(|pid| pid.to_string()).collect();
-
-                let file_message = FileMessage {
-                    sender: (*local_peer_id).to_string(),
-                    password: password.to_string(),
-                    file_name: chunk_filename.clone(),
-                    data: buffer,
-                    receivers, // Now we include the receivers field
-                };
-
-                let serialized = serde_json::to_vec(&file_message)?;
-
-                if let Err(e) = send_file_to_peers(swarm, &target_peers, topic, serialized.clone()).await {
-                    println!(
-                        "Failed to send file chunk '{}' to peers {:?}: {:?}",
-                        chunk_filename, target_peers, e
-                    );
-                } else {
-                    println!(
-                        "File chunk '{}' successfully sent to peers: {:?}",
-                        chunk_filename, target_peers
-                    );
+            }
+            let files = match file_operations::unique_upload_names(files) {
+                Ok(files) => files,
+                Err(e) => {
+                    let _ = reply.send(Err(NodeError::Config(e.to_string())));
+                    return;
                 }
-
+            };
+            let mut job = PutJob {
+                id: state.next_put_job,
+                redundancy,
+                files: Vec::new(),
+                uploading: files.len(),
+                outstanding: HashSet::new(),
+                stored: HashSet::new(),
+                confirmed: Vec::new(),
+                reply,
+            };
+            state.next_put_job += 1;
+            if state.peer_scores.is_empty() {
+                println!("No valid peers to send the file.");
+                job.files = files
+                    .iter()
+                    .map(|path| (path.display().to_string(), Vec::new()))
+                    .collect();
+                job.finish();
+                return;
             }
-        } else {
-            println!("No file selected for upload.");
-        }
-    } else if trimmed == "@check_scores" {
-        println!("Current peer scores:");
-        for (peer_id, score) in peer_scores {
-            println!("Peer ID: {}, Score: {}", peer_id, score);
-        }
-    } else if trimmed == "@check_logs" {
-        let logs = file_transfer_logs.lock().unwrap();
-        let results = search_transfer_log(&logs, None, None, None);
-        for log in results {
-            println!("Log: {:?}", log);
-        }
-    } else if trimmed.starts_with("@download ") {
-        let parts: Vec<&str> = trimmed.split_whitespace().collect();
-        if parts.len() < 2 {
-            println!("Usage: @download <filename>");
-        } else {
-            let requested_file = parts[1].to_string();
-
-            let request_msg = FileRequestMessage {
-                sender: (*local_peer_id).to_string(),
-                password: password.to_string(),
-                file_name: requested_file.clone(),
+            let coding = match redundancy {
+                Redundancy::Replication(_) => None,
+                Redundancy::ErasureCoding(coding) => Some(coding),
             };
-
-            let serialized = serde_json::to_vec(&request_msg)?;
-            if let Err(e) = swarm
-                .behaviour_mut()
-                .gossipsub
-                .publish(topic.clone(), serialized)
+            upload_pipeline::spawn(
+                job.id,
+                files,
+                chunking,
+                coding,
+                state.keys.clone(),
+                state.chunk_store.clone(),
+                state.pipeline_events.clone(),
+            );
+            state.put_jobs.push(job);
+        }
+        NodeCommand::Get {
+            file_name,
+            output,
+            reply,
+        } => {
+            // Waits before starting, as a download whose chunks are all
+            // held locally completes right away.
+            state
+                .get_waiters
+                .entry(file_name.clone())
+                .or_default()
+                .push(GetWaiter { output, reply });
+            start_download(&file_name, swarm, state);
+        }
+        NodeCommand::Downloads { reply } => {
+            let dls = state.downloads.lock().unwrap();
+            let mut downloads: Vec<DownloadInfo> = dls
+                .iter()
+                .map(|(file_name, download_state)| {
+                    download_info(file_name, download_state, &state.download_journal)
+                })
+                .chain(state.failed_downloads.values().map(|failed| failed.info.clone()))
+                .collect();
+            downloads.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            let _ = reply.send(downloads);
+        }
+        NodeCommand::CancelDownload { file_name, reply } => {
+            if state.failed_downloads.remove(&file_name).is_some() {
+                let _ = reply.send(Ok(()));
+                return;
+            }
+            let cancelled = state.downloads.lock().unwrap().remove(&file_name).is_some();
+            if !cancelled {
+                let _ = reply.send(Err(NodeError::NotFound(format!(
+                    "no download of '{}' in progress",
+                    file_name
+                ))));
+                return;
+            }
+            state.download_journal.finish(&file_name);
+            for waiter in state.get_waiters.remove(&file_name).unwrap_or_default() {
+                let _ = waiter.reply.send(Err(NodeError::Cancelled(format!(
+                    "download of '{}'",
+                    file_name
+                ))));
+            }
+            println!("Cancelled download of '{}'.", file_name);
+            let _ = reply.send(Ok(()));
+        }
+        NodeCommand::List { reply } => {
+            let manifests = match storage_manager::list_manifests(&state.layout) {
+                Ok(manifests) => manifests,
+                Err(e) => {
+                    let _ = reply.send(Err(NodeError::Storage(e)));
+                    return;
+                }
+            };
+            let mut files: Vec<FileEntry> = manifests
+                .into_iter()
+                .map(|manifest| FileEntry {
+                    replication: replication_status(swarm, state, &manifest.file_name),
+                    local_chunks: manifest
+                        .chunk_hashes
+                        .iter()
+                        .filter(|hash| state.chunk_store.has(hash))
+                        .count(),
+                    total_chunks: manifest.total_chunks(),
+                    total_length: manifest.total_length,
+                    file_name: manifest.file_name,
+                    publisher: manifest.publisher,
+                })
+                .collect();
+            files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            let _ = reply.send(Ok(files));
+        }
+        NodeCommand::SetReplication {
+            file_name,
+            replication_factor,
+            reply,
+        } => {
+            let result = if replication_factor == 0 {
+                Err(NodeError::Config(
+                    "the replication factor must be at least 1".to_string(),
+                ))
+            } else if state
+                .replication
+                .set_replication_factor(&file_name, replication_factor)
             {
-                println!("Failed to publish file request: {:?}", e);
-            } else {
-                println!("File request for '{}' sent to network.", requested_file);
-                let mut dls = downloads.lock().unwrap();
-                dls.insert(
-                    requested_file,
-                    DownloadState {
-                        total_chunks: 0,
-                        received_chunks: 0,
-                        chunks_data: HashMap::new(),
-                    },
+                println!(
+                    "Keeping {} replica(s) of each chunk of '{}'",
+                    replication_factor, file_name
                 );
+                audit_replication(swarm, state);
+                Ok(())
+            } else {
+                Err(NodeError::NotFound(format!(
+                    "'{}' was not uploaded from this node",
+                    file_name
+                )))
+            };
+            let _ = reply.send(result);
+        }
+        NodeCommand::Peers { reply } => {
+            let mut peers: Vec<PeerInfo> = state
+                .authenticated_peers
+                .iter()
+                .map(|peer_id| PeerInfo {
+                    peer_id: peer_id.to_string(),
+                    connected: swarm.is_connected(peer_id),
+                    direct: state.reachability.is_direct(peer_id),
+                    score: state.peer_scores.get(peer_id).copied(),
+                })
+                .collect();
+            peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+            let _ = reply.send(peers);
+        }
+        NodeCommand::Scores { reply } => {
+            let mut scores: Vec<PeerScore> = state
+                .peer_scores
+                .iter()
+                .map(|(peer_id, score)| PeerScore {
+                    peer_id: peer_id.to_string(),
+                    score: *score,
+                })
+                .collect();
+            scores.sort_by(|a, b| b.score.total_cmp(&a.score));
+            let _ = reply.send(scores);
+        }
+        NodeCommand::Logs { query, reply } => {
+            let logs = state.file_transfer_logs.lock().unwrap();
+            let results = search_transfer_log(
+                &logs,
+                query.peer_id.as_deref(),
+                query.file_name.as_deref(),
+                query.date.map(|date| date.and_time(NaiveTime::MIN).and_utc()),
+            );
+            let _ = reply.send(results);
+        }
+        NodeCommand::Publish { text, reply } => {
+            let message = WireMessage::Chat { text: text.clone() };
+            let result = publish_gossip(swarm, &state.topic, &state.keys, message);
+            if result.is_ok() {
+                println!("Message sent: {}", text);
             }
+            let _ = reply.send(result);
         }
-    } else {
-        let message_with_password = format!("{}:{}", password, trimmed);
-        if let Err(e) = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(topic.clone(), message_with_password.as_bytes())
-        {
-            println!("Publish error: {:?}", e);
+    }
+}
+
+/// Looks up the holders of `file_name` in the DHT and tracks it as an
+/// in-progress download, unless it already is one. A manifest stored from
+/// an earlier attempt is used right away, so only missing chunks are fetched.
+fn start_download(file_name: &str, swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    if state.downloads.lock().unwrap().contains_key(file_name) {
+        return;
+    }
+    state.failed_downloads.remove(file_name);
+    state.download_journal.start(file_name);
+    let query_id = swarm
+        .behaviour_mut()
+        .kademlia
+        .get_providers(discovery::manifest_key(&state.keys, file_name));
+    state.provider_queries.insert(
+        query_id,
+        ProviderQuery::Manifest {
+            file_name: file_name.to_string(),
+            found: 0,
+        },
+    );
+    println!("Looking up holders of '{}' in the DHT.", file_name);
+    let mut download_state = DownloadState {
+        manifest: None,
+        deadline: Instant::now() + state.download_deadline,
+        scheduler: DownloadScheduler::default(),
+        located: HashSet::new(),
+        exhausted: HashSet::new(),
+        assembling: false,
+    };
+    let manifest = storage_manager::load_manifest(&state.layout, file_name);
+    let resumed = manifest.is_some();
+    if let Some(manifest) = manifest {
+        set_download_manifest(&mut download_state, manifest, state.chunk_store.as_ref());
+    }
+    state
+        .downloads
+        .lock()
+        .unwrap()
+        .insert(file_name.to_string(), download_state);
+    if resumed {
+        advance_download(swarm, state, file_name);
+    }
+}
+
+/// Describes a download for listing.
+fn download_info(
+    file_name: &str,
+    download_state: &DownloadState,
+    journal: &DownloadJournal,
+) -> DownloadInfo {
+    let chunk_hashes = download_state
+        .manifest
+        .as_ref()
+        .map(|manifest| manifest.chunk_hashes.as_slice())
+        .unwrap_or_default();
+    let progress = download_state.scheduler.progress(chunk_hashes);
+    DownloadInfo {
+        file_name: file_name.to_string(),
+        started_at: journal
+            .get(file_name)
+            .map(|entry| entry.started_at)
+            .unwrap_or_else(Utc::now),
+        received_chunks: progress.received,
+        total_chunks: download_state.manifest.as_ref().map(|_| progress.total),
+        in_flight: progress.in_flight,
+        state: DownloadPhase::Downloading,
+    }
+}
+
+/// Ends a download that cannot finish: answers its waiters, reports it on
+/// the event stream and lists it as failed until it is garbage collected.
+/// Waiters get `NotFound` when no peer ever sent the manifest.
+fn fail_download(state: &mut NodeState, file_name: &str, reason: String) {
+    let Some(download_state) = state.downloads.lock().unwrap().remove(file_name) else {
+        return;
+    };
+    let missing_chunks: Vec<usize> = download_state
+        .manifest
+        .iter()
+        .flat_map(|manifest| manifest.chunk_hashes.iter().enumerate())
+        .filter(|(_, hash)| !download_state.scheduler.is_received(hash))
+        .map(|(index, _)| index)
+        .collect();
+    println!(
+        "Download of '{}' failed: {} ({} chunk(s) missing).",
+        file_name,
+        reason,
+        missing_chunks.len()
+    );
+    for waiter in state.get_waiters.remove(file_name).unwrap_or_default() {
+        let error = if download_state.manifest.is_some() {
+            NodeError::DownloadFailed {
+                reason: reason.clone(),
+                missing_chunks: missing_chunks.clone(),
+            }
         } else {
-            println!("Message sent: {}", trimmed);
-        }
+            NodeError::NotFound(reason.clone())
+        };
+        let _ = waiter.reply.send(Err(error));
     }
-    Ok(())
+
+    let mut info = download_info(file_name, &download_state, &state.download_journal);
+    info.in_flight = 0;
+    info.state = DownloadPhase::Failed {
+        reason: reason.clone(),
+        missing_chunks: missing_chunks.clone(),
+    };
+    remember_failed_download(state, info);
+    state.emit(NodeEvent::DownloadFailed {
+        file_name: file_name.to_string(),
+        reason,
+        missing_chunks,
+    });
 }
-async fn send_file_to_peers(
-    swarm: &mut libp2p::Swarm<MyBehaviour>,
-    target_peers: &[PeerId],
-    topic: &gossipsub::IdentTopic,
-    serialized_message: Vec<u8>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Add all target peers as explicit peers to ensure they are included in the Gossipsub mesh
-    for peer_id in target_peers {
-        if !swarm.is_connected(peer_id) {
-            println!("Peer {} is not connected. Skipping.", peer_id);
-            continue;
-        }
-        swarm.behaviour_mut().gossipsub.add_explicit_peer(peer_id);
-        println!("Added peer {} as an explicit peer.", peer_id);
+
+fn remember_failed_download(state: &mut NodeState, info: DownloadInfo) {
+    state.download_journal.finish(&info.file_name);
+    state.failed_downloads.insert(
+        info.file_name.clone(),
+        FailedDownload {
+            info,
+            failed_at: Instant::now(),
+        },
+    );
+}
+
+/// Restarts the downloads left unfinished when the node last stopped, once
+/// a peer is around to look up their holders.
+fn resume_downloads(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    for file_name in std::mem::take(&mut state.unresumed_downloads) {
+        println!("Resuming download of '{}'.", file_name);
+        start_download(&file_name, swarm, state);
     }
+}
 
-    // Wait briefly to allow the protocol to adjust
-    tokio::time::sleep(Duration::from_secs(2)).await;
+/// Gives a download its manifest and counts the chunks and parity shards
+/// already in the chunk store as received.
+fn set_download_manifest(
+    download_state: &mut DownloadState,
+    manifest: FileManifest,
+    store: &dyn ChunkStore,
+) {
+    let held: Vec<String> = manifest
+        .shard_hashes()
+        .into_iter()
+        .filter(|hash| store.has(hash))
+        .collect();
+    println!(
+        "Receiving '{}' with {} chunks ({} bytes, {}), {} already held.",
+        manifest.file_name,
+        manifest.total_chunks(),
+        manifest.total_length,
+        manifest.mime_hint,
+        held.len()
+    );
+    for hash in held {
+        download_state.scheduler.on_received(&hash);
+    }
+    download_state.manifest = Some(manifest);
+}
 
-    // Publish the message to the topic once
-    let result = swarm
-        .behaviour_mut()
-        .gossipsub
-        .publish(topic.clone(), serialized_message);
+/// Completes a download whose chunks are all held, or fetches the rest.
+fn advance_download(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    file_name: &str,
+) {
+    let status = try_complete_download(&mut state.downloads.lock().unwrap(), file_name);
+    match status {
+        DownloadStatus::InProgress => request_missing_chunks(swarm, state, file_name),
+        status => settle_download(swarm, state, file_name, status),
+    }
+}
 
-    // Remove all explicit peers to restore normal routing
-    for peer_id in target_peers {
-        swarm.behaviour_mut().gossipsub.remove_explicit_peer(peer_id);
+/// Asks `provider` for the manifest of `file_name`, once it is authenticated.
+fn fetch_manifest(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    provider: PeerId,
+    file_name: &str,
+) {
+    if !state.authenticated_peers.contains(&provider) {
+        state
+            .deferred_manifest_fetches
+            .entry(provider)
+            .or_default()
+            .push(file_name.to_string());
+        start_handshake(swarm, state, provider);
+        return;
     }
+    let request_id = swarm.behaviour_mut().file_transfer.send_request(
+        &provider,
+        FileRequest::FetchManifest {
+            file_name: file_name.to_string(),
+        },
+    );
+    state.pending_requests.insert(
+        request_id,
+        PendingRequest::Manifest {
+            file_name: file_name.to_string(),
+        },
+    );
+}
 
-    match result {
-        Ok(_) => {
-            println!("File successfully sent to peers: {:?}", target_peers);
-            Ok(())
+fn check_redundancy(redundancy: Redundancy) -> Result<(), NodeError> {
+    match redundancy {
+        Redundancy::Replication(0) => Err(NodeError::Config(
+            "the replication factor must be at least 1".to_string(),
+        )),
+        Redundancy::ErasureCoding(coding)
+            if coding.data_shards == 0
+                || coding.parity_shards == 0
+                || coding.data_shards + coding.parity_shards > erasure::MAX_SHARDS =>
+        {
+            Err(NodeError::Config(format!(
+                "erasure coding needs at least 1 data and 1 parity shard and at most {} in total",
+                erasure::MAX_SHARDS
+            )))
         }
-        Err(e) => {
-            println!("Failed to send file to peers {:?}: {:?}", target_peers, e);
-            Err(e.into())
-        }
-    }
-}
-// async fn send_file_to_peer(
-//     swarm: &mut libp2p::Swarm<MyBehaviour>,
-//     target_peer_id: &PeerId,
-//     topic: &gossipsub::IdentTopic,
-//     serialized_message: Vec<u8>,
-// ) -> Result<(), Box<dyn Error>> {
-//     if !swarm.is_connected(target_peer_id) {
-//         println!("Peer {} is not connected.", target_peer_id);
-//         return Err("Target peer is not connected".into());
-//     }
-
-//     swarm
-//         .behaviour_mut()
-//         .gossipsub
-//         .add_explicit_peer(target_peer_id);
-//     println!("Added peer {} as an explicit peer.", target_peer_id);
-
-//     tokio::time::sleep(Duration::from_secs(2)).await;
-
-//     let result = swarm
-//         .behaviour_mut()
-//         .gossipsub
-//         .publish(topic.clone(), serialized_message);
-
-//     swarm
-//         .behaviour_mut()
-//         .gossipsub
-//         .remove_explicit_peer(target_peer_id);
-
-//     match result {
-//         Ok(_) => {
-//             println!("File successfully sent to peer {}.", target_peer_id);
-//             Ok(())
-//         }
-//         Err(e) => {
-//             println!("Failed to send file to peer {}: {:?}", target_peer_id, e);
-//             Err(e.into())
-//         }
-//     }
-// }
+        _ => Ok(()),
+    }
+}
 
-async fn handle_swarm_event(
-    event: SwarmEvent<MyBehaviourEvent>,
+fn check_chunking(chunking: Chunking) -> Result<(), NodeError> {
+    match chunking {
+        Chunking::ContentDefined {
+            min_size,
+            avg_size,
+            max_size,
+        } if min_size < chunker::MIN_CHUNK_SIZE
+            || min_size > avg_size
+            || avg_size > max_size
+            || max_size > chunker::MAX_CHUNK_SIZE =>
+        {
+            Err(NodeError::Config(format!(
+                "content-defined chunk sizes must satisfy {} <= min <= avg <= max <= {}",
+                chunker::MIN_CHUNK_SIZE,
+                chunker::MAX_CHUNK_SIZE
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Resolves an upload argument (a path, directory or glob pattern) to the
+/// files it names.
+fn resolve_upload_paths(spec: &str) -> Result<Vec<PathBuf>, NodeError> {
+    file_operations::resolve_upload_paths(spec).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => NodeError::NotFound(e.to_string()),
+        _ => NodeError::Storage(e),
+    })
+}
+
+/// Queues the chunks an upload pipeline stored for sending, and publishes
+/// the manifest of every file it finished.
+fn handle_pipeline_event(
+    event: PipelineEvent,
     swarm: &mut libp2p::Swarm<MyBehaviour>,
-    password: &str,
-    peer_scores: &mut HashMap<PeerId, f64>,
-    local_peer_id: &PeerId,
-    topic: &gossipsub::IdentTopic,
-    file_transfer_logs: SharedFileTransferLogs,
-    downloads: SharedDownloads,
-) -> Result<(), Box<dyn Error>> {
+    state: &mut NodeState,
+) {
     match event {
-        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-            for (peer_id, _) in list {
-                println!("mDNS discovered a new peer: {}", peer_id);
-                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
-                let initial_score: f64 = rand::thread_rng().gen_range(3.0..=8.0);
-                peer_scores.entry(peer_id).or_insert(initial_score);
+        PipelineEvent::Chunk {
+            job,
+            file_name,
+            index,
+            hash,
+        } => {
+            let Some(Redundancy::Replication(replication_factor)) = put_job_redundancy(state, job)
+            else {
+                return;
+            };
+            provide(swarm, state, discovery::chunk_key(&state.keys, &hash));
+            let label = format!("{} [chunk {}]", file_name, index + 1);
+            // A chunk an earlier upload shares with this one is only sent
+            // to make up for the replicas it lacks.
+            let holders = live_holders(swarm, state, &hash);
+            if holders.len() >= replication_factor {
+                println!("File chunk {} already held by {} peer(s)", label, holders.len());
+                if let Some(put_job) = state.put_jobs.iter_mut().find(|put_job| put_job.id == job) {
+                    put_job.stored.insert(hash.clone());
+                    put_job
+                        .confirmed
+                        .extend(holders.into_iter().map(|peer| (hash.clone(), peer)));
+                }
+            } else {
+                let candidates: HashMap<PeerId, f64> = state
+                    .peer_scores
+                    .iter()
+                    .filter(|(peer_id, _)| !holders.contains(peer_id))
+                    .map(|(peer_id, score)| (*peer_id, *score))
+                    .collect();
+                state.store_queue.push_back(QueuedStore {
+                    job: Some(job),
+                    label,
+                    file_name,
+                    chunk_hash: hash,
+                    target_peers: select_peers(
+                        &candidates,
+                        &state.reachability,
+                        replication_factor - holders.len(),
+                    ),
+                });
             }
         }
-        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
-            for (peer_id, _) in list {
-                println!("mDNS discovered peer has expired: {}", peer_id);
-                swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .remove_explicit_peer(&peer_id);
+        PipelineEvent::Stripe {
+            job,
+            file_name,
+            stripe,
+            shards,
+        } => {
+            // Every chunk and parity shard of a stripe goes to a different
+            // peer where there are enough peers.
+            let target_peers = select_peers(&state.peer_scores, &state.reachability, shards.len());
+            if target_peers.len() < shards.len() {
+                println!(
+                    "Only {} peer(s) for the {} shards of stripe {} of '{}'; some peers get several",
+                    target_peers.len(),
+                    shards.len(),
+                    stripe + 1,
+                    file_name
+                );
+            }
+            let shard_count = shards.len();
+            for (i, hash) in shards.into_iter().enumerate() {
+                provide(swarm, state, discovery::chunk_key(&state.keys, &hash));
+                let holders = live_holders(swarm, state, &hash);
+                if let Some(holder) = holders.first() {
+                    println!(
+                        "Shard {}/{} of stripe {} of '{}' already held by {}",
+                        i + 1,
+                        shard_count,
+                        stripe + 1,
+                        file_name,
+                        holder
+                    );
+                    if let Some(put_job) = state.put_jobs.iter_mut().find(|put_job| put_job.id == job) {
+                        put_job.stored.insert(hash.clone());
+                        put_job.confirmed.push((hash, *holder));
+                    }
+                    continue;
+                }
+                state.store_queue.push_back(QueuedStore {
+                    job: Some(job),
+                    file_name: file_name.clone(),
+                    label: format!(
+                        "{} [stripe {}, shard {}/{}]",
+                        file_name,
+                        stripe + 1,
+                        i + 1,
+                        shard_count
+                    ),
+                    chunk_hash: hash,
+                    target_peers: target_peers
+                        .get(i % target_peers.len().max(1))
+                        .copied()
+                        .into_iter()
+                        .collect(),
+                });
             }
         }
-        SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-            propagation_source: peer_id,
-            message_id: id,
-            message,
-        })) => {
-            handle_received_message(
-                &peer_id,
-                &id,
-                &message,
-                password,
-                peer_scores,
-                local_peer_id,
-                swarm,
-                topic,
-                file_transfer_logs,
-                downloads.clone(),
-            )
-            .await?;
-        }
-        SwarmEvent::NewListenAddr { address, .. } => {
-            println!("Local node is listening on {}", address);
+        PipelineEvent::Finished {
+            job,
+            file_name,
+            result,
+        } => {
+            let Some(redundancy) = put_job_redundancy(state, job) else {
+                return;
+            };
+            let chunk_hashes = match result {
+                Ok(chunked) if chunked.chunks.is_empty() => {
+                    println!("No chunks created, file might be empty!");
+                    Vec::new()
+                }
+                Ok(chunked) => {
+                    match publish_upload(swarm, state, job, &file_name, chunked, redundancy) {
+                        Ok(chunk_hashes) => chunk_hashes,
+                        Err(e) => {
+                            println!("Failed to upload '{}': {}", file_name, e);
+                            Vec::new()
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to upload '{}': {}", file_name, e);
+                    Vec::new()
+                }
+            };
+            if let Some(put_job) = state.put_jobs.iter_mut().find(|put_job| put_job.id == job) {
+                put_job.files.push((file_name, chunk_hashes));
+                put_job.uploading -= 1;
+            }
         }
-        _ => {}
     }
-    Ok(())
+    send_queued_stores(swarm, state);
 }
 
-async fn handle_received_message(
-    peer_id: &PeerId,
-    message_id: &gossipsub::MessageId,
-    message: &gossipsub::Message,
-    password: &str,
-    peer_scores: &mut HashMap<PeerId, f64>,
-    local_peer_id: &PeerId,
+/// The connected, authenticated peers known to store `chunk_hash`.
+fn live_holders(
+    swarm: &libp2p::Swarm<MyBehaviour>,
+    state: &NodeState,
+    chunk_hash: &str,
+) -> BTreeSet<PeerId> {
+    state
+        .replication
+        .holders(chunk_hash)
+        .into_iter()
+        .filter(|peer| swarm.is_connected(peer) && state.authenticated_peers.contains(peer))
+        .collect()
+}
+
+fn put_job_redundancy(state: &NodeState, job: u64) -> Option<Redundancy> {
+    state
+        .put_jobs
+        .iter()
+        .find(|put_job| put_job.id == job)
+        .map(|put_job| put_job.redundancy)
+}
+
+/// Publishes the signed manifest of a file the upload pipeline finished and
+/// hands its chunks to the replication manager, which keeps them spread
+/// from then on. Returns the digests of the file's chunks and parity shards.
+fn publish_upload(
     swarm: &mut libp2p::Swarm<MyBehaviour>,
-    topic: &gossipsub::IdentTopic,
-    file_transfer_logs: SharedFileTransferLogs,
-    downloads: SharedDownloads,
-) -> Result<(), Box<dyn Error>> {
-    let data = &message.data;
+    state: &mut NodeState,
+    job: u64,
+    file_name: &str,
+    chunked: ChunkedFile,
+    redundancy: Redundancy,
+) -> Result<Vec<String>, NodeError> {
+    let replicas_per_shard = match redundancy {
+        Redundancy::Replication(replication_factor) => replication_factor,
+        Redundancy::ErasureCoding(_) => 1,
+    };
+    let manifest = FileManifest::new(file_name, &chunked.chunks, chunked.erasure, &state.keypair)?;
+    let chunk_hashes = manifest.shard_hashes();
+    storage_manager::save_manifest(&state.layout, &manifest)?;
+    state
+        .replication
+        .track(file_name, &chunk_hashes, manifest.stripes(), replicas_per_shard);
+    if let Some(put_job) = state.put_jobs.iter_mut().find(|put_job| put_job.id == job) {
+        state.replication.add_holders(&put_job.confirmed);
+        put_job.confirmed.clear();
+    }
+    provide(swarm, state, discovery::manifest_key(&state.keys, file_name));
+    let merkle_root = manifest.merkle_root.clone();
+    let announcement = WireMessage::Announce {
+        file_name: file_name.to_string(),
+        merkle_root: manifest.merkle_root,
+        publisher: manifest.publisher,
+    };
+    if let Err(e) = publish_gossip(swarm, &state.topic, &state.keys, announcement) {
+        println!("Failed to announce '{}': {:?}", file_name, e);
+    } else {
+        println!("Announced '{}' (Merkle root {})", file_name, merkle_root);
+    }
+    Ok(chunk_hashes)
+}
 
-    if let Ok(file_message) = serde_json::from_slice::<FileMessage>(data) {
-        let local_peer_id_str = local_peer_id.to_string();
-        if !file_message.receivers.contains(&local_peer_id_str) {
+/// Sends queued chunks while fewer than
+/// [`upload_pipeline::MAX_STORES_IN_FLIGHT`] store requests are outstanding,
+/// then replies to every `Put` command that is done.
+fn send_queued_stores(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    while state
+        .pending_requests
+        .values()
+        .filter(|request| matches!(request, PendingRequest::Store { .. }))
+        .count()
+        < upload_pipeline::MAX_STORES_IN_FLIGHT
+    {
+        let Some(queued) = state.store_queue.pop_front() else {
+            break;
+        };
+        let data = match state.chunk_store.get(&queued.chunk_hash) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to send file chunk {}: {}", queued.label, e);
+                continue;
+            }
+        };
+        let request = FileRequest::StoreChunk {
+            file_name: queued.file_name,
+            chunk_hash: queued.chunk_hash.clone(),
+            data,
+        };
+        let sent = send_file_to_peers(swarm, state, &queued.target_peers, request);
+        let sent_to: Vec<PeerId> = sent.iter().map(|(peer_id, _)| *peer_id).collect();
+        if let Some(job) = state
+            .put_jobs
+            .iter_mut()
+            .find(|job| Some(job.id) == queued.job)
+        {
+            job.outstanding
+                .extend(sent.into_iter().map(|(_, request_id)| request_id));
+        }
+        if sent_to.is_empty() {
             println!(
-                "Received file chunk '{}' from peer {} but local peer is not a listed receiver. Ignoring.",
-                file_message.file_name, peer_id
+                "Failed to send file chunk {}: none of {:?} are connected",
+                queued.label, queued.target_peers
             );
-            // Here you can choose to return or just ignore the message.
-            return Ok(());
-        }
-        if file_message.password == password {
-            let local_peer_id_str = format!("./{}/", local_peer_id);
-            std::fs::create_dir_all(&local_peer_id_str)?;
-            let file_path = format!("{}/{}", local_peer_id_str, file_message.file_name);
-            std::fs::write(&file_path, &file_message.data)?;
-
+        } else {
             println!(
-                "Received file chunk '{}' from peer {} and saved to '{}'",
-                file_message.file_name, peer_id, file_path
+                "File chunk {} ({}) sent to peers: {:?}",
+                queued.label, queued.chunk_hash, sent_to
             );
+        }
+    }
 
-            if let Some((original_name, i, n)) = parse_chunk_filename(&file_message.file_name) {
-                let mut dls = downloads.lock().unwrap();
-                if let Some(download_state) = dls.get_mut(&original_name) {
-                    // MODIFIED: Only increment received_chunks if this chunk wasn't already known
-                    let is_new_chunk = !download_state.chunks_data.contains_key(&i);
-                    download_state
-                        .chunks_data
-                        .entry(i)
-                        .or_insert(file_message.data.clone());
-
-                    if download_state.total_chunks == 0 {
-                        download_state.total_chunks = n;
-                    }
+    let (done, pending): (Vec<_>, Vec<_>) = state.put_jobs.drain(..).partition(|job| {
+        job.uploading == 0
+            && job.outstanding.is_empty()
+            && !state
+                .store_queue
+                .iter()
+                .any(|queued| queued.job == Some(job.id))
+    });
+    state.put_jobs = pending;
+    for job in done {
+        job.finish();
+    }
+}
 
-                    if is_new_chunk {
-                        download_state.received_chunks += 1;
-                        println!(
-                            "Download progress for '{}': {}/{}",
-                            original_name,
-                            download_state.received_chunks,
-                            download_state.total_chunks
-                        );
-                    }
+fn replication_status(
+    swarm: &libp2p::Swarm<MyBehaviour>,
+    state: &NodeState,
+    file_name: &str,
+) -> Option<ReplicationStatus> {
+    Some(ReplicationStatus {
+        replication_factor: state.replication.replication_factor(file_name)?,
+        live_replicas: state
+            .replication
+            .live_replicas(file_name, |peer| swarm.is_connected(peer))?,
+    })
+}
 
-                    // MODIFIED: Attempt reassembly only if all unique chunks are received
-                    if download_state.total_chunks > 0
-                        && download_state.received_chunks == download_state.total_chunks
-                    {
-                        // Verify all chunks are present
-                        let all_present = (1..=download_state.total_chunks)
-                            .all(|x| download_state.chunks_data.contains_key(&x));
-
-                        if all_present {
-                            let output_path = format!("{}/{}", local_peer_id_str, original_name);
-                            let mut output_file = fs::File::create(&output_path)?;
-                            for chunk_idx in 1..=download_state.total_chunks {
-                                let chunk_data =
-                                    download_state.chunks_data.get(&chunk_idx).unwrap();
-                                output_file.write_all(chunk_data)?;
-                            }
-                            println!("Download complete: {}", output_path);
-
-                            dls.remove(&original_name);
-                        } else {
-                            // MODIFIED: Do not remove download_state. Just note it's not complete.
-                            println!(
-                                "Not all chunks received yet for '{}', waiting for missing chunks.",
-                                original_name
-                            );
-                            // We do nothing else, waiting for missing chunks to arrive
-                        }
-                    }
-                }
-            }
+/// Asks every live replica which of its recorded chunks it still holds,
+/// and queues chunks with fewer live replicas than their file asks for to
+/// be copied to peers that do not hold them yet.
+fn audit_replication(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    let under_replicated = state
+        .replication
+        .under_replicated(|peer| swarm.is_connected(peer) && state.authenticated_peers.contains(peer));
+    let mut stranded = 0;
+    for chunk in under_replicated {
+        let in_flight = state.pending_requests.values().any(|request| {
+            matches!(request, PendingRequest::Store { chunk_hash, .. } if *chunk_hash == chunk.chunk_hash)
+        }) || state
+            .store_queue
+            .iter()
+            .any(|queued| queued.chunk_hash == chunk.chunk_hash);
+        if in_flight {
+            continue;
+        }
+        if !state.chunk_store.has(&chunk.chunk_hash) {
+            println!(
+                "Cannot re-replicate chunk '{}' of '{}': not in the chunk store",
+                chunk.chunk_hash, chunk.file_name
+            );
+            continue;
+        }
+        let candidates: HashMap<PeerId, f64> = state
+            .peer_scores
+            .iter()
+            .filter(|(peer_id, _)| !chunk.excluded.contains(peer_id) && swarm.is_connected(peer_id))
+            .map(|(peer_id, score)| (*peer_id, *score))
+            .collect();
+        let target_peers = select_peers(&candidates, &state.reachability, chunk.missing);
+        if target_peers.is_empty() {
+            stranded += 1;
+            continue;
+        }
+        println!(
+            "Re-replicating chunk '{}' of '{}' to {:?}",
+            chunk.chunk_hash, chunk.file_name, target_peers
+        );
+        state.store_queue.push_back(QueuedStore {
+            job: None,
+            label: format!("{} [re-replica]", chunk.file_name),
+            file_name: chunk.file_name,
+            chunk_hash: chunk.chunk_hash,
+            target_peers,
+        });
+    }
+    send_queued_stores(swarm, state);
+    if stranded > 0 {
+        println!(
+            "{} chunk(s) are under-replicated with no other peer to copy them to",
+            stranded
+        );
+    }
 
-            update_peer_score(peer_scores, peer_id, 0.5);
+    let by_holder = state.replication.chunks_by_holder();
+    state
+        .audit_cursors
+        .retain(|peer_id, _| by_holder.contains_key(peer_id));
+    for (peer_id, held) in by_holder {
+        if !swarm.is_connected(&peer_id) || !state.authenticated_peers.contains(&peer_id) {
+            continue;
+        }
+        // A holder of many chunks is asked about one batch per audit, in
+        // turn, so no request outgrows the message size limit.
+        let cursor = state.audit_cursors.entry(peer_id).or_insert(0);
+        if *cursor >= held.len() {
+            *cursor = 0;
+        }
+        let chunk_hashes: Vec<String> = held
+            .iter()
+            .skip(*cursor)
+            .take(replication::AUDIT_BATCH)
+            .cloned()
+            .collect();
+        *cursor += chunk_hashes.len();
+        let request_id = swarm.behaviour_mut().file_transfer.send_request(
+            &peer_id,
+            FileRequest::CheckChunks {
+                chunk_hashes: chunk_hashes.clone(),
+            },
+        );
+        state
+            .pending_requests
+            .insert(request_id, PendingRequest::Audit { chunk_hashes });
+    }
+}
 
-            let log_message = FileTransferLog {
-                peer_id: local_peer_id.to_string(),
-                file_name: file_message.file_name.clone(),
-                timestamp: chrono::Utc::now(),
-                password: password.to_string(),
-            };
+/// Sends a chunk store request directly to each connected peer in
+/// `target_peers` over the file transfer protocol.
+/// Returns the peers the request was sent to, with the id of each request.
+fn send_file_to_peers(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    target_peers: &[PeerId],
+    request: FileRequest,
+) -> Vec<(PeerId, OutboundRequestId)> {
+    let FileRequest::StoreChunk {
+        file_name,
+        chunk_hash,
+        ..
+    } = &request
+    else {
+        return Vec::new();
+    };
+
+    let mut sent_to = Vec::new();
+    for peer_id in target_peers {
+        if !swarm.is_connected(peer_id) {
+            println!("Peer {} is not connected. Skipping.", peer_id);
+            continue;
+        }
+        let request_id = swarm
+            .behaviour_mut()
+            .file_transfer
+            .send_request(peer_id, request.clone());
+        state.pending_requests.insert(
+            request_id,
+            PendingRequest::Store {
+                file_name: file_name.clone(),
+                chunk_hash: chunk_hash.clone(),
+            },
+        );
+        sent_to.push((*peer_id, request_id));
+    }
+    sent_to
+}
 
-            if let Ok(serialized_log) = serde_json::to_vec(&log_message) {
-                if let Err(e) = swarm
+async fn handle_swarm_event(
+    event: SwarmEvent<MyBehaviourEvent>,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<(), NodeError> {
+    match event {
+        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+            for (peer_id, address) in list {
+                println!("mDNS discovered a new peer: {}", peer_id);
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+                start_handshake(swarm, state, peer_id);
+            }
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+            for (peer_id, _) in list {
+                println!("mDNS discovered peer has expired: {}", peer_id);
+                swarm
                     .behaviour_mut()
                     .gossipsub
-                    .publish(topic.clone(), serialized_log)
+                    .remove_explicit_peer(&peer_id);
+            }
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            propagation_source: peer_id,
+            message_id: id,
+            message,
+        })) => {
+            if state.authenticated_peers.contains(&peer_id) {
+                handle_received_message(&peer_id, &id, &message, swarm, state).await?;
+            } else {
+                println!(
+                    "Ignoring message {} relayed by unauthenticated peer: {}",
+                    id, peer_id
+                );
+            }
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::FileTransfer(event)) => {
+            handle_file_transfer_event(event, swarm, state)?;
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Auth(event)) => {
+            handle_auth_event(event, swarm, state);
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(
+            kad::Event::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetProviders(result),
+                step,
+                ..
+            },
+        )) => {
+            handle_providers_result(id, result, step.last, swarm, state);
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            // Peers found through identify become reachable in the DHT.
+            if info.protocols.contains(&discovery::KAD_PROTOCOL) {
+                for address in info.listen_addrs {
+                    swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+                }
+            }
+            if info.protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+                state.reachability.add_relay(peer_id);
+                state.reachability.reserve_if_private(swarm);
+            }
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Autonat(autonat::Event::StatusChanged {
+            new,
+            ..
+        })) => {
+            println!("NAT status: {:?}", new);
+            state.reachability.reserve_if_private(swarm);
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::RelayClient(
+            relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal: false,
+                ..
+            },
+        )) => {
+            println!("Reachable through relay {}", relay_peer_id);
+        }
+        SwarmEvent::Behaviour(MyBehaviourEvent::Dcutr(dcutr::Event {
+            remote_peer_id,
+            result,
+        })) => match result {
+            Ok(_) => println!("Upgraded relayed connection to {} to a direct one", remote_peer_id),
+            Err(e) => println!("Hole punching to {} failed: {}", remote_peer_id, e),
+        },
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint,
+            ..
+        } => {
+            state.reachability.on_connection_established(peer_id, &endpoint);
+            if let Some(mut address) = state.static_peers.on_connected(connection_id, peer_id) {
+                if static_peers::peer_id_of(&address).is_some() {
+                    address.pop();
+                }
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+                // The first static peer is the way into the wider DHT.
+                if !state.dht_bootstrapped
+                    && discovery::bootstrap(&mut swarm.behaviour_mut().kademlia)
                 {
-                    println!("Failed to publish file log info: {:?}", e);
-                } else {
-                    println!("File log info published to topic.");
+                    println!("Bootstrapping the DHT through {}", peer_id);
+                    state.dht_bootstrapped = true;
                 }
             }
-        } else {
-            println!("Received file with invalid password from peer: {}", peer_id);
-            update_peer_score(peer_scores, peer_id, -0.01);
-        }
-    } else if let Ok(log_message) = serde_json::from_slice::<FileTransferLog>(data) {
-        if log_message.password == password {
-            let mut logs = file_transfer_logs.lock().unwrap();
-            logs.push(FileTransferLog {
-                peer_id: log_message.peer_id.clone(),
-                file_name: log_message.file_name.clone(),
-                timestamp: log_message.timestamp,
-                password: log_message.password,
+            if state.authenticated_peers.contains(&peer_id) {
+                // Peers dropped from the score table while disconnected rejoin
+                // it on reconnection; they proved themselves earlier in the session.
+                let initial_score: f64 = rand::thread_rng().gen_range(3.0..=8.0);
+                state.peer_scores.entry(peer_id).or_insert(initial_score);
+            } else {
+                // Peers reached through the DHT were never seen by mDNS.
+                start_handshake(swarm, state, peer_id);
+            }
+        }
+        SwarmEvent::OutgoingConnectionError { connection_id, .. } => {
+            state.static_peers.on_dial_failed(connection_id);
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            endpoint,
+            num_established,
+            ..
+        } => {
+            state
+                .reachability
+                .on_connection_closed(peer_id, &endpoint, num_established);
+            if num_established == 0 {
+                state.static_peers.on_disconnected(peer_id);
+            }
+        }
+        SwarmEvent::NewListenAddr { address, .. } => {
+            println!("Local node is listening on {}", address);
+            state.emit(NodeEvent::Listening {
+                address: address.clone(),
             });
+            // A relay hands its external addresses to the peers reserving a
+            // slot, and denies reservations without any, so one run for
+            // others is assumed reachable on the addresses it listens on.
+            if swarm.behaviour().relay_server.is_enabled()
+                && !address.iter().any(|p| p == libp2p::multiaddr::Protocol::P2pCircuit)
+            {
+                swarm.add_external_address(address);
+            }
+        }
+        SwarmEvent::ListenerClosed {
+            addresses,
+            reason: Err(e),
+            ..
+        } => {
+            println!("Stopped listening on {:?}: {}", addresses, e);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_received_message(
+    peer_id: &PeerId,
+    message_id: &gossipsub::MessageId,
+    message: &gossipsub::Message,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<(), NodeError> {
+    let data = match state.keys.open_message(&message.data) {
+        Ok(data) => data,
+        Err(e) => {
+            println!(
+                "Received message that could not be decrypted ({}) from peer: {}",
+                e, peer_id
+            );
+            update_peer_score(&mut state.peer_scores, peer_id, -0.01);
+            return Ok(());
+        }
+    };
+
+    let wire_message = match wire::decode(&data) {
+        Ok(wire_message) => wire_message,
+        Err(WireError::UnsupportedVersion(version)) => {
+            println!(
+                "Rejected message {} from peer {}: protocol version {} is not supported (expected {})",
+                message_id,
+                peer_id,
+                version,
+                wire::PROTOCOL_VERSION
+            );
+            update_peer_score(&mut state.peer_scores, peer_id, -0.1);
+            return Ok(());
+        }
+        Err(e) => {
+            println!("Received undecodable message {} from peer {}: {}", message_id, peer_id, e);
+            update_peer_score(&mut state.peer_scores, peer_id, -0.01);
+            return Ok(());
+        }
+    };
+
+    match wire_message {
+        WireMessage::TransferLog(log_message) => {
             println!(
                 "Updated logs with new entry: peer_id={}, file_name={}",
                 log_message.peer_id, log_message.file_name
             );
-        } else {
+            if let Err(e) = storage_manager::append_transfer_log(&state.layout, &log_message) {
+                println!("Failed to persist transfer log entry: {}", e);
+            }
+            state.file_transfer_logs.lock().unwrap().push(log_message.clone());
+            state.emit(NodeEvent::TransferLogged(log_message));
+        }
+        WireMessage::Announce {
+            file_name,
+            merkle_root,
+            publisher,
+        } => {
+            if !manifest::is_plain_file_name(&file_name) {
+                println!("Ignoring announcement of {:?} from peer {}", file_name, peer_id);
+                update_peer_score(&mut state.peer_scores, peer_id, -0.5);
+                return Ok(());
+            }
+            // The original publisher of the announcement holds the file,
+            // and may differ from the peer that relayed it to us.
+            let holder = message.source.unwrap_or(*peer_id);
+            let known = storage_manager::load_manifest(&state.layout, &file_name)
+                .is_some_and(|manifest| manifest.merkle_root == merkle_root);
+            let downloading = state.downloads.lock().unwrap().contains_key(&file_name);
+            if !known || downloading {
+                println!(
+                    "Peer {} announced '{}' published by {}. Fetching its manifest.",
+                    holder, file_name, publisher
+                );
+                fetch_manifest(swarm, state, holder, &file_name);
+            }
+        }
+        WireMessage::Chat { text } => {
             println!(
-                "Received file transfer log with invalid password from peer: {}",
-                peer_id
+                "Got message: '{}' with id: {} from peer: {}",
+                text, message_id, peer_id
             );
+            state.emit(NodeEvent::Message {
+                peer_id: message.source.unwrap_or(*peer_id),
+                text,
+            });
         }
-    } else if let Ok(request_msg) = serde_json::from_slice::<FileRequestMessage>(data) {
-        if request_msg.password == password {
-            let local_peer_id_str = format!("./{}/", local_peer_id);
-            if let Some((total_chunks, chunk_files)) =
-                find_chunks_for_file(&local_peer_id_str, &request_msg.file_name)
+    }
+
+    Ok(())
+}
+
+/// Verifies and stores a manifest announced or served by `peer` on behalf of
+/// `holder`, and fetches the chunks `holder` can serve if the file is being
+/// downloaded.
+fn accept_manifest(
+    peer: &PeerId,
+    holder: PeerId,
+    manifest: FileManifest,
+    available_chunks: ShardBitmap,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<(), NodeError> {
+    if let Err(reason) = manifest.verify() {
+        println!(
+            "Rejected manifest for '{}' from peer {}: {}",
+            manifest.file_name, peer, reason
+        );
+        update_peer_score(&mut state.peer_scores, peer, -0.5);
+        return Ok(());
+    }
+
+    storage_manager::save_manifest(&state.layout, &manifest)?;
+    if manifest
+        .shard_hashes()
+        .iter()
+        .any(|hash| state.chunk_store.has(hash))
+    {
+        provide(swarm, state, discovery::manifest_key(&state.keys, &manifest.file_name));
+    }
+
+    let file_name = manifest.file_name.clone();
+    {
+        let mut dls = state.downloads.lock().unwrap();
+        let Some(download_state) = dls.get_mut(&file_name) else {
+            return Ok(());
+        };
+        for chunk_hash in available_chunks.shard_hashes(&manifest) {
+            download_state.exhausted.remove(&chunk_hash);
+            download_state.scheduler.add_holder(&chunk_hash, holder);
+        }
+        if download_state.manifest.is_none() {
+            set_download_manifest(download_state, manifest, state.chunk_store.as_ref());
+        }
+    }
+    advance_download(swarm, state, &file_name);
+    Ok(())
+}
+
+/// Chunks and parity shards of a download still worth fetching: every
+/// missing chunk of a replicated file, or for an erasure-coded file just
+/// enough shards of each stripe to rebuild it, passing over shards nobody
+/// could be found to serve.
+fn wanted_shards(download_state: &DownloadState, manifest: &FileManifest) -> Vec<String> {
+    let missing = |hash: &&String| !download_state.scheduler.is_received(hash);
+    let Some(erasure) = &manifest.erasure else {
+        return manifest.chunk_hashes.iter().filter(missing).cloned().collect();
+    };
+    let total_chunks = manifest.total_chunks();
+    let mut wanted = Vec::new();
+    for stripe in 0..erasure.stripe_count(total_chunks) {
+        let chunks = &manifest.chunk_hashes[erasure.stripe_chunks(stripe, total_chunks)];
+        let parity = &erasure.parity_hashes[erasure.stripe_parity(stripe)];
+        let received = chunks
+            .iter()
+            .chain(parity)
+            .filter(|hash| download_state.scheduler.is_received(hash))
+            .count();
+        wanted.extend(
+            chunks
+                .iter()
+                .chain(parity)
+                .filter(missing)
+                .filter(|hash| !download_state.exhausted.contains(*hash))
+                .take(chunks.len().saturating_sub(received))
+                .cloned(),
+        );
+    }
+    wanted
+}
+
+/// Starts a DHT lookup for every wanted shard of a download that no known
+/// peer holds.
+fn locate_unheld_chunks(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    file_name: &str,
+) {
+    let mut dls = state.downloads.lock().unwrap();
+    let Some(download_state) = dls.get_mut(file_name) else {
+        return;
+    };
+    let Some(manifest) = &download_state.manifest else {
+        return;
+    };
+    let unheld: Vec<String> = wanted_shards(download_state, manifest)
+        .into_iter()
+        .filter(|hash| {
+            !download_state.located.contains(hash) && !download_state.scheduler.has_holders(hash)
+        })
+        .collect();
+    for chunk_hash in unheld {
+        let query_id = swarm
+            .behaviour_mut()
+            .kademlia
+            .get_providers(discovery::chunk_key(&state.keys, &chunk_hash));
+        state.provider_queries.insert(
+            query_id,
+            ProviderQuery::Chunk {
+                file_name: file_name.to_string(),
+                chunk_hash: chunk_hash.clone(),
+            },
+        );
+        download_state.located.insert(chunk_hash);
+    }
+}
+
+/// Follows up on providers found in the DHT: manifest providers are asked
+/// for the manifest, chunk providers become holders of their chunk. A file
+/// nobody provides fails its waiting downloads.
+fn handle_providers_result(
+    query_id: kad::QueryId,
+    result: kad::GetProvidersResult,
+    last: bool,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) {
+    if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = result {
+        let providers: Vec<PeerId> = providers
+            .into_iter()
+            .filter(|provider| *provider != state.local_peer_id)
+            .collect();
+        match state.provider_queries.get_mut(&query_id) {
+            Some(ProviderQuery::Manifest { file_name, found }) => {
+                *found += providers.len();
+                let file_name = file_name.clone();
+                for provider in providers {
+                    println!("Peer {} provides '{}'", provider, file_name);
+                    fetch_manifest(swarm, state, provider, &file_name);
+                }
+            }
+            Some(ProviderQuery::Chunk {
+                file_name,
+                chunk_hash,
+            }) => {
+                let file_name = file_name.clone();
+                {
+                    let mut dls = state.downloads.lock().unwrap();
+                    if let Some(download_state) = dls.get_mut(&file_name) {
+                        if !providers.is_empty() {
+                            download_state.exhausted.remove(chunk_hash);
+                        }
+                        for provider in providers {
+                            download_state.scheduler.add_holder(chunk_hash, provider);
+                        }
+                    }
+                }
+                request_missing_chunks(swarm, state, &file_name);
+            }
+            None => {}
+        }
+    }
+
+    if !last {
+        return;
+    }
+    match state.provider_queries.remove(&query_id) {
+        Some(ProviderQuery::Manifest {
+            file_name,
+            found: 0,
+        }) => fail_unprovided_download(state, &file_name),
+        Some(ProviderQuery::Chunk {
+            file_name,
+            chunk_hash,
+        }) => {
             {
-                let meta_msg = FileMetadataMessage {
-                    sender: local_peer_id.to_string(),
-                    password: password.to_string(),
-                    file_name: request_msg.file_name.clone(),
-                    total_chunks,
-                };
-
-                if let Ok(serialized_meta) = serde_json::to_vec(&meta_msg) {
-                    if let Err(e) = swarm
-                        .behaviour_mut()
-                        .gossipsub
-                        .publish(topic.clone(), serialized_meta)
-                    {
-                        println!("Failed to publish file metadata: {:?}", e);
-                    } else {
-                        println!("Sent file metadata for '{}'", request_msg.file_name);
+                let mut dls = state.downloads.lock().unwrap();
+                if let Some(download_state) = dls.get_mut(&file_name) {
+                    if !download_state.scheduler.has_holders(&chunk_hash) {
+                        download_state.scheduler.record_failure(&chunk_hash);
+                        download_state.exhausted.insert(chunk_hash);
                     }
                 }
+            }
+            request_missing_chunks(swarm, state, &file_name);
+        }
+        _ => {}
+    }
+}
 
-                for cf in chunk_files {
-                    let chunk_data = fs::read(format!("{}{}", local_peer_id_str, cf))?;
-                    let receivers = vec![peer_id.to_string()];
-                    let file_message = FileMessage {
-                        sender: local_peer_id.to_string(),
-                        password: password.to_string(),
-                        file_name: cf.clone(),
-                        data: chunk_data,
-                        receivers:receivers,
-                    };
-                    if let Ok(serialized_chunk) = serde_json::to_vec(&file_message) {
-                        if let Err(e) = swarm
-                            .behaviour_mut()
-                            .gossipsub
-                            .publish(topic.clone(), serialized_chunk)
-                        {
-                            println!("Failed to publish file chunk '{}': {:?}", cf, e);
-                        } else {
-                            println!("Sent file chunk '{}'", cf);
-                        }
+/// Fails the waiting downloads of a file nobody provides, unless a peer
+/// announced its manifest meanwhile.
+fn fail_unprovided_download(state: &mut NodeState, file_name: &str) {
+    let announced = state
+        .downloads
+        .lock()
+        .unwrap()
+        .get(file_name)
+        .is_some_and(|download_state| download_state.manifest.is_some());
+    if !announced {
+        fail_download(state, file_name, format!("no peer provides '{}'", file_name));
+    }
+}
+
+fn handle_file_transfer_event(
+    event: request_response::Event<FileRequest, FileResponse>,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<(), NodeError> {
+    match event {
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Request {
+                request, channel, ..
+            },
+        } => {
+            let response = handle_file_request(&peer, request, swarm, state).unwrap_or_else(|e| {
+                println!("Failed to serve request from peer {}: {}", peer, e);
+                FileResponse::Rejected {
+                    reason: e.to_string(),
+                }
+            });
+            if swarm
+                .behaviour_mut()
+                .file_transfer
+                .send_response(channel, response)
+                .is_err()
+            {
+                println!("Failed to respond to peer {}: connection closed", peer);
+            }
+        }
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Response {
+                request_id,
+                response,
+            },
+        } => match state.pending_requests.remove(&request_id) {
+            Some(PendingRequest::Store {
+                file_name,
+                chunk_hash,
+            }) => match response {
+                FileResponse::Stored => {
+                    println!(
+                        "Peer {} stored chunk '{}' of '{}'",
+                        peer, chunk_hash, file_name
+                    );
+                    state.replication.add_holder(&chunk_hash, peer);
+                    settle_store_request(swarm, state, request_id, Some((chunk_hash, peer)));
+                }
+                other => {
+                    println!(
+                        "Peer {} did not store chunk '{}' of '{}': {:?}",
+                        peer, chunk_hash, file_name, other
+                    );
+                    settle_store_request(swarm, state, request_id, None);
+                }
+            },
+            Some(PendingRequest::Manifest { file_name }) => match response {
+                FileResponse::Manifest {
+                    manifest,
+                    available_chunks,
+                } if manifest.file_name == file_name => {
+                    accept_manifest(&peer, peer, manifest, available_chunks, swarm, state)?;
+                }
+                other => {
+                    println!(
+                        "Peer {} could not serve the manifest of '{}': {:?}",
+                        peer, file_name, other
+                    );
+                }
+            },
+            Some(PendingRequest::Audit { chunk_hashes }) => match response {
+                FileResponse::Holding {
+                    chunk_hashes: held,
+                } => {
+                    let lost: Vec<String> = chunk_hashes
+                        .into_iter()
+                        .filter(|hash| !held.contains(hash))
+                        .collect();
+                    if !lost.is_empty() {
+                        println!(
+                            "Peer {} no longer holds {} chunk(s); they will be copied elsewhere",
+                            peer,
+                            lost.len()
+                        );
+                        state.replication.remove_holder(&lost, &peer);
                     }
                 }
+                other => {
+                    println!("Peer {} could not be audited: {:?}", peer, other);
+                }
+            },
+            Some(PendingRequest::Fetch {
+                file_name,
+                chunk_hash,
+            }) => {
+                if let FileResponse::Chunk { data, .. } = response {
+                    handle_fetched_chunk(&peer, &file_name, &chunk_hash, data, swarm, state)?;
+                } else {
+                    println!(
+                        "Peer {} could not serve chunk '{}' of '{}': {:?}",
+                        peer, chunk_hash, file_name, response
+                    );
+                    forget_holder(state, &file_name, &chunk_hash, &peer);
+                }
+                request_missing_chunks(swarm, state, &file_name);
+            }
+            None => {}
+        },
+        request_response::Event::OutboundFailure {
+            peer,
+            request_id,
+            error,
+            ..
+        } => {
+            println!("Request to peer {} failed: {}", peer, error);
+            match state.pending_requests.remove(&request_id) {
+                Some(PendingRequest::Fetch {
+                    file_name,
+                    chunk_hash,
+                }) => {
+                    forget_holder(state, &file_name, &chunk_hash, &peer);
+                    request_missing_chunks(swarm, state, &file_name);
+                }
+                Some(PendingRequest::Store { .. }) => {
+                    settle_store_request(swarm, state, request_id, None);
+                }
+                Some(PendingRequest::Manifest { .. } | PendingRequest::Audit { .. }) | None => {}
+            }
+        }
+        request_response::Event::InboundFailure { peer, error, .. } => {
+            println!("Inbound request from peer {} failed: {}", peer, error);
+        }
+        request_response::Event::ResponseSent { .. } => {}
+    }
+    Ok(())
+}
+
+/// Records the answer to a store request, sends the next queued upload
+/// chunks and replies to every `Put` command that is done.
+fn settle_store_request(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    request_id: OutboundRequestId,
+    stored: Option<(String, PeerId)>,
+) {
+    for job in &mut state.put_jobs {
+        if job.outstanding.remove(&request_id) {
+            if let Some((chunk_hash, peer)) = &stored {
+                job.stored.insert(chunk_hash.clone());
+                if job.uploading > 0 {
+                    job.confirmed.push((chunk_hash.clone(), *peer));
+                }
+            }
+        }
+    }
+    send_queued_stores(swarm, state);
+}
+
+/// Challenges `peer_id` to prove knowledge of the topic password, unless it
+/// is already authenticated or a challenge to it is still outstanding.
+fn start_handshake(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState, peer_id: PeerId) {
+    if state.authenticated_peers.contains(&peer_id)
+        || state
+            .pending_challenges
+            .values()
+            .any(|(peer, _)| *peer == peer_id)
+    {
+        return;
+    }
+    let challenge = auth::new_challenge();
+    let nonce = challenge.nonce.clone();
+    let request_id = swarm.behaviour_mut().auth.send_request(&peer_id, challenge);
+    state.pending_challenges.insert(request_id, (peer_id, nonce));
+}
+
+fn handle_auth_event(
+    event: request_response::Event<AuthChallenge, AuthProof>,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) {
+    match event {
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Request {
+                request, channel, ..
+            },
+        } => {
+            if request.nonce.len() == auth::NONCE_SIZE {
+                let proof = state
+                    .keys
+                    .auth_proof(&request.nonce, &state.local_peer_id, &peer);
+                if swarm
+                    .behaviour_mut()
+                    .auth
+                    .send_response(channel, AuthProof { proof })
+                    .is_err()
+                {
+                    println!("Failed to answer challenge from peer {}", peer);
+                }
+            }
+            // Authentication is mutual: challenge peers that reach us first.
+            start_handshake(swarm, state, peer);
+        }
+        request_response::Event::Message {
+            peer,
+            message: request_response::Message::Response {
+                request_id,
+                response,
+            },
+        } => {
+            let Some((_, nonce)) = state.pending_challenges.remove(&request_id) else {
+                return;
+            };
+            if state
+                .keys
+                .verify_auth_proof(&nonce, &peer, &state.local_peer_id, &response.proof)
+            {
+                println!("Peer {} authenticated", peer);
+                state.authenticated_peers.insert(peer);
+                state.emit(NodeEvent::PeerAuthenticated { peer_id: peer });
+                resume_downloads(swarm, state);
+                for file_name in state
+                    .deferred_manifest_fetches
+                    .remove(&peer)
+                    .unwrap_or_default()
+                {
+                    fetch_manifest(swarm, state, peer, &file_name);
+                }
+                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                let initial_score: f64 = rand::thread_rng().gen_range(3.0..=8.0);
+                state.peer_scores.entry(peer).or_insert(initial_score);
             } else {
+                reject_peer(swarm, state, &peer, "invalid challenge response");
+            }
+        }
+        request_response::Event::OutboundFailure {
+            peer,
+            request_id,
+            error,
+            ..
+        } => {
+            state.pending_challenges.remove(&request_id);
+            if matches!(error, request_response::OutboundFailure::UnsupportedProtocols) {
+                reject_peer(swarm, state, &peer, "does not speak the auth protocol");
+            } else {
+                println!("Authentication handshake with peer {} failed: {}", peer, error);
+            }
+        }
+        request_response::Event::InboundFailure { peer, error, .. } => {
+            println!("Inbound challenge from peer {} failed: {}", peer, error);
+        }
+        request_response::Event::ResponseSent { .. } => {}
+    }
+}
+
+/// Blacklists a peer that failed authentication so gossipsub drops it.
+fn reject_peer(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    peer_id: &PeerId,
+    reason: &str,
+) {
+    println!("Peer {} failed authentication ({}). Blacklisting.", peer_id, reason);
+    state.authenticated_peers.remove(peer_id);
+    state.peer_scores.remove(peer_id);
+    let gossipsub = &mut swarm.behaviour_mut().gossipsub;
+    gossipsub.remove_explicit_peer(peer_id);
+    gossipsub.blacklist_peer(peer_id);
+    state.emit(NodeEvent::PeerRejected {
+        peer_id: *peer_id,
+        reason: reason.to_string(),
+    });
+}
+
+/// Serves a store or fetch request received from `peer`.
+fn handle_file_request(
+    peer: &PeerId,
+    request: FileRequest,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<FileResponse, NodeError> {
+    if !state.authenticated_peers.contains(peer) {
+        println!("Rejecting file request from unauthenticated peer: {}", peer);
+        return Ok(FileResponse::Rejected {
+            reason: "peer is not authenticated".to_string(),
+        });
+    }
+
+    match request {
+        FileRequest::StoreChunk {
+            file_name,
+            chunk_hash,
+            data,
+        } => {
+            if !manifest::is_plain_file_name(&file_name) {
+                return Ok(reject_file_name(peer, &file_name));
+            }
+            // Only peers holding the topic key can produce chunks that decrypt.
+            if state.keys.decrypt_chunk(&data).is_err() {
                 println!(
-                    "Requested file '{}' not found locally.",
-                    request_msg.file_name
+                    "Received chunk not encrypted with the topic key from peer: {}",
+                    peer
                 );
+                update_peer_score(&mut state.peer_scores, peer, -0.01);
+                return Ok(FileResponse::Rejected {
+                    reason: "chunk is not encrypted with the topic key".to_string(),
+                });
             }
-        } else {
-            println!(
-                "Received file request with invalid password from peer: {}",
-                peer_id
-            );
-        }
-    } else if let Ok(meta_msg) = serde_json::from_slice::<FileMetadataMessage>(data) {
-        if meta_msg.password == password {
-            let mut dls = downloads.lock().unwrap();
-            if let Some(download_state) = dls.get_mut(&meta_msg.file_name) {
-                download_state.total_chunks = meta_msg.total_chunks;
+
+            let actual_hash = chunker::hash_chunk(&data);
+            if actual_hash != chunk_hash {
                 println!(
-                    "Receiving '{}' with {} chunks.",
-                    meta_msg.file_name, meta_msg.total_chunks
+                    "Chunk of '{}' from peer {} failed digest verification (expected {}, got {}). Discarding.",
+                    file_name, peer, chunk_hash, actual_hash
                 );
+                update_peer_score(&mut state.peer_scores, peer, -0.5);
+                return Ok(FileResponse::Rejected {
+                    reason: "digest mismatch".to_string(),
+                });
             }
-        } else {
+
+            state.chunk_store.put(&actual_hash, &data)?;
             println!(
-                "Received file metadata with invalid password from peer: {}",
-                peer_id
+                "Received chunk '{}' of '{}' from peer {} and stored it",
+                chunk_hash, file_name, peer
             );
+            provide(swarm, state, discovery::chunk_key(&state.keys, &chunk_hash));
+            if storage_manager::load_manifest(&state.layout, &file_name)
+                .is_some_and(|manifest| manifest.shard_hashes().contains(&chunk_hash))
+            {
+                provide(swarm, state, discovery::manifest_key(&state.keys, &file_name));
+            }
+
+            update_peer_score(&mut state.peer_scores, peer, 0.5);
+            state.emit(NodeEvent::ChunkStored {
+                peer_id: *peer,
+                file_name: file_name.clone(),
+                chunk_hash: chunk_hash.clone(),
+            });
+
+            let log_message = FileTransferLog {
+                peer_id: state.local_peer_id.to_string(),
+                file_name,
+                chunk_hash,
+                timestamp: chrono::Utc::now(),
+            };
+            let log_message = WireMessage::TransferLog(log_message);
+            if let Err(e) = publish_gossip(swarm, &state.topic, &state.keys, log_message) {
+                println!("Failed to publish file log info: {:?}", e);
+            } else {
+                println!("File log info published to topic.");
+            }
+
+            Ok(FileResponse::Stored)
         }
-    } else {
-        let message_content = String::from_utf8_lossy(&message.data);
-        if message_content.starts_with(password) {
-            let actual_message = &message_content[password.len() + 1..];
-            println!(
-                "Got message: '{}' with id: {} from peer: {}",
-                actual_message, message_id, peer_id
-            );
-        } else {
-            println!(
-                "Received message with invalid password from peer: {}",
-                peer_id
-            );
+        FileRequest::FetchManifest { file_name } => {
+            if !manifest::is_plain_file_name(&file_name) {
+                return Ok(reject_file_name(peer, &file_name));
+            }
+            match storage_manager::find_chunks_for_file(
+                &state.layout,
+                state.chunk_store.as_ref(),
+                &file_name,
+            ) {
+                Some((manifest, available_chunks)) => {
+                    println!("Sent manifest of '{}' to peer {}", file_name, peer);
+                    Ok(FileResponse::Manifest {
+                        manifest,
+                        available_chunks,
+                    })
+                }
+                None => Ok(FileResponse::NotFound),
+            }
         }
+        FileRequest::FetchChunk { chunk_hash } => {
+            // Chunks are stored encrypted, so serving them reveals only ciphertext.
+            match state.chunk_store.get(&chunk_hash) {
+                Ok(data) => {
+                    println!("Sent file chunk '{}' to peer {}", chunk_hash, peer);
+                    Ok(FileResponse::Chunk { chunk_hash, data })
+                }
+                Err(_) => Ok(FileResponse::NotFound),
+            }
+        }
+        FileRequest::CheckChunks { chunk_hashes } => Ok(FileResponse::Holding {
+            chunk_hashes: chunk_hashes
+                .into_iter()
+                .filter(|hash| state.chunk_store.has(hash))
+                .collect(),
+        }),
     }
+}
 
+/// Answers a request naming a file by a path rather than a plain name, which
+/// only a misbehaving peer sends.
+fn reject_file_name(peer: &PeerId, file_name: &str) -> FileResponse {
+    println!("Rejecting request for file {:?} from peer {}", file_name, peer);
+    FileResponse::Rejected {
+        reason: "invalid file name".to_string(),
+    }
+}
+
+/// Verifies and records a chunk fetched for an in-progress download.
+fn handle_fetched_chunk(
+    peer: &PeerId,
+    file_name: &str,
+    chunk_hash: &str,
+    data: Vec<u8>,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) -> Result<(), NodeError> {
+    let actual_hash = chunker::hash_chunk(&data);
+    if actual_hash != chunk_hash {
+        println!(
+            "Chunk of '{}' from peer {} failed digest verification (expected {}, got {}). Discarding.",
+            file_name, peer, chunk_hash, actual_hash
+        );
+        update_peer_score(&mut state.peer_scores, peer, -0.5);
+        forget_holder(state, file_name, chunk_hash, peer);
+        return Ok(());
+    }
+
+    state.chunk_store.put(&actual_hash, &data)?;
+    provide(swarm, state, discovery::chunk_key(&state.keys, &actual_hash));
+    update_peer_score(&mut state.peer_scores, peer, 0.5);
+
+    let (progress, status) = {
+        let mut dls = state.downloads.lock().unwrap();
+        let Some(download_state) = dls.get_mut(file_name) else {
+            return Ok(());
+        };
+        if !download_state.scheduler.on_received(&actual_hash) {
+            return Ok(());
+        }
+        let progress = download_state
+            .manifest
+            .as_ref()
+            .map(|manifest| download_state.scheduler.progress(&manifest.chunk_hashes));
+        let status = try_complete_download(&mut dls, file_name);
+        (progress, status)
+    };
+    if let Some(progress) = progress {
+        println!("Download progress for '{}': {}", file_name, progress);
+        state.emit(NodeEvent::DownloadProgress {
+            file_name: file_name.to_string(),
+            received_chunks: progress.received,
+            total_chunks: progress.total,
+        });
+    }
+    settle_download(swarm, state, file_name, status);
     Ok(())
 }
 
-fn parse_chunk_filename(name: &str) -> Option<(String, usize, usize)> {
-    let idx = name.rfind('(')?;
-    let meta = &name[idx + 1..name.len() - 1];
-    let parts: Vec<_> = meta.split("-of-").collect();
-    if parts.len() != 2 {
-        return None;
+/// Starts reassembling a download whose chunks are all in, writing it
+/// straight to the output of the first waiter that gave one, or answers the
+/// waiters of a download that completed or was discarded.
+fn settle_download(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    file_name: &str,
+    status: DownloadStatus,
+) {
+    match status {
+        DownloadStatus::InProgress | DownloadStatus::Refetch(_) => return,
+        DownloadStatus::Assemble(manifest, received) => {
+            let target = state
+                .get_waiters
+                .get(file_name)
+                .and_then(|waiters| waiters.iter().find_map(|waiter| waiter.output.clone()))
+                .unwrap_or_else(|| state.layout.download_path(file_name));
+            start_assembly(state, file_name, *manifest, received, target);
+            return;
+        }
+        DownloadStatus::Complete(path) => {
+            provide(swarm, state, discovery::manifest_key(&state.keys, file_name));
+            for waiter in state.get_waiters.remove(file_name).unwrap_or_default() {
+                let target = waiter
+                    .output
+                    .unwrap_or_else(|| state.layout.download_path(file_name));
+                if target == path {
+                    let _ = waiter.reply.send(Ok(path.clone()));
+                    continue;
+                }
+                // Waiters wanting the file somewhere else get a copy, made
+                // off the event loop too.
+                let source = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = fs::copy(&source, &target)
+                        .map(|_| target)
+                        .map_err(NodeError::Storage);
+                    let _ = waiter.reply.send(result);
+                });
+            }
+            state.emit(NodeEvent::DownloadCompleted {
+                file_name: file_name.to_string(),
+                path,
+            });
+        }
+        DownloadStatus::Discarded(reason) => {
+            for waiter in state.get_waiters.remove(file_name).unwrap_or_default() {
+                let _ = waiter.reply.send(Err(NodeError::Integrity(reason.clone())));
+            }
+            remember_failed_download(
+                state,
+                DownloadInfo {
+                    file_name: file_name.to_string(),
+                    started_at: state
+                        .download_journal
+                        .get(file_name)
+                        .map(|entry| entry.started_at)
+                        .unwrap_or_else(Utc::now),
+                    received_chunks: 0,
+                    total_chunks: None,
+                    in_flight: 0,
+                    state: DownloadPhase::Failed {
+                        reason: reason.clone(),
+                        missing_chunks: Vec::new(),
+                    },
+                },
+            );
+            state.emit(NodeEvent::DownloadFailed {
+                file_name: file_name.to_string(),
+                reason,
+                missing_chunks: Vec::new(),
+            });
+        }
     }
-    let i = parts[0].parse::<usize>().ok()?;
-    let n = parts[1].parse::<usize>().ok()?;
+    state.download_journal.finish(file_name);
+}
 
-    let original_name = (&name[..idx]).to_string();
-    Some((original_name, i, n))
+/// Drops `peer` as a holder of `chunk_hash` after a failed or invalid fetch so
+/// the chunk can be requested from someone else once its backoff is over. A
+/// shard left without holders after its DHT lookup finished is passed over
+/// until the backoff allows another lookup.
+fn forget_holder(state: &mut NodeState, file_name: &str, chunk_hash: &str, peer: &PeerId) {
+    let lookup_running = state.provider_queries.values().any(|query| {
+        matches!(query, ProviderQuery::Chunk { chunk_hash: hash, .. } if hash == chunk_hash)
+    });
+    let mut dls = state.downloads.lock().unwrap();
+    if let Some(download_state) = dls.get_mut(file_name) {
+        download_state.scheduler.record_failure(chunk_hash);
+        if !download_state.scheduler.forget_holder(chunk_hash, peer)
+            && download_state.located.contains(chunk_hash)
+            && !lookup_running
+        {
+            download_state.exhausted.insert(chunk_hash.to_string());
+        }
+    }
 }
 
-fn find_chunks_for_file(dir: &str, file_name: &str) -> Option<(usize, Vec<String>)> {
-    let entries = fs::read_dir(dir).ok()?;
-    let mut chunk_files = Vec::new();
-    let mut total_chunks = 0;
+/// Runs every second: hands timed-out fetches to other holders, looks up
+/// holders again for chunks whose backoff is over, fails downloads past
+/// their deadline or missing chunks nobody can serve, and forgets failed
+/// downloads listed for longer than [`FAILED_DOWNLOAD_RETENTION`].
+fn drive_downloads(swarm: &mut libp2p::Swarm<MyBehaviour>, state: &mut NodeState) {
+    let mut stalled_peers = Vec::new();
+    let mut failed = Vec::new();
+    let mut active = Vec::new();
+    {
+        let mut dls = state.downloads.lock().unwrap();
+        for (file_name, download_state) in dls.iter_mut() {
+            if download_state.assembling {
+                continue;
+            }
+            for (chunk_hash, peer) in download_state.scheduler.take_timed_out() {
+                println!(
+                    "Peer {} did not send chunk '{}' of '{}' in time. Reassigning it.",
+                    peer, chunk_hash, file_name
+                );
+                stalled_peers.push(peer);
+            }
+            if Instant::now() >= download_state.deadline {
+                failed.push((file_name.clone(), "the download deadline passed".to_string()));
+                continue;
+            }
 
-    for entry in entries {
-        if let Ok(e) = entry {
-            let fname = e.file_name().to_string_lossy().to_string();
-            if fname.starts_with(file_name) {
-                if let Some((_, _, n)) = parse_chunk_filename(&fname) {
-                    if n > total_chunks {
-                        total_chunks = n;
-                    }
-                    chunk_files.push(fname);
+            let scheduler = &download_state.scheduler;
+            let relocate: Vec<String> = download_state
+                .exhausted
+                .iter()
+                .filter(|hash| scheduler.retry_due(hash) && !scheduler.is_lost(hash))
+                .cloned()
+                .collect();
+            for chunk_hash in relocate {
+                download_state.exhausted.remove(&chunk_hash);
+                download_state.located.remove(&chunk_hash);
+            }
+
+            if download_state
+                .manifest
+                .as_ref()
+                .is_some_and(|manifest| has_unrecoverable_chunks(download_state, manifest))
+            {
+                failed.push((
+                    file_name.clone(),
+                    "no peer could serve the missing chunks".to_string(),
+                ));
+                continue;
+            }
+            active.push(file_name.clone());
+        }
+    }
+
+    for peer in &stalled_peers {
+        update_peer_score(&mut state.peer_scores, peer, -0.1);
+    }
+    for (file_name, reason) in failed {
+        fail_download(state, &file_name, reason);
+    }
+    for file_name in active {
+        request_missing_chunks(swarm, state, &file_name);
+    }
+    state
+        .failed_downloads
+        .retain(|_, failed| failed.failed_at.elapsed() < FAILED_DOWNLOAD_RETENTION);
+}
+
+/// Whether a download lost a chunk of a replicated file, or for an
+/// erasure-coded file more shards of a stripe than its parity can rebuild.
+fn has_unrecoverable_chunks(download_state: &DownloadState, manifest: &FileManifest) -> bool {
+    let scheduler = &download_state.scheduler;
+    let lost = |hash: &&String| !scheduler.is_received(hash) && scheduler.is_lost(hash);
+    let Some(erasure) = &manifest.erasure else {
+        return manifest.chunk_hashes.iter().any(|hash| lost(&hash));
+    };
+    let total_chunks = manifest.total_chunks();
+    (0..erasure.stripe_count(total_chunks)).any(|stripe| {
+        let chunks = &manifest.chunk_hashes[erasure.stripe_chunks(stripe, total_chunks)];
+        let parity = &erasure.parity_hashes[erasure.stripe_parity(stripe)];
+        chunks.iter().chain(parity).filter(lost).count() > parity.len()
+    })
+}
+
+/// Sends the fetches the download's scheduler assigns to holders of its
+/// wanted shards, and looks up holders of the shards nobody is known to hold.
+fn request_missing_chunks(
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+    file_name: &str,
+) {
+    locate_unheld_chunks(swarm, state, file_name);
+    let mut dls = state.downloads.lock().unwrap();
+    let Some(download_state) = dls.get_mut(file_name) else {
+        return;
+    };
+    let Some(manifest) = &download_state.manifest else {
+        return;
+    };
+
+    let wanted = wanted_shards(download_state, manifest);
+    for (chunk_hash, holder) in download_state.scheduler.assign(&wanted, &state.peer_scores) {
+        let request_id = swarm.behaviour_mut().file_transfer.send_request(
+            &holder,
+            FileRequest::FetchChunk {
+                chunk_hash: chunk_hash.clone(),
+            },
+        );
+        state.pending_requests.insert(
+            request_id,
+            PendingRequest::Fetch {
+                file_name: file_name.to_string(),
+                chunk_hash,
+            },
+        );
+    }
+}
+
+/// Hands a download over for reassembly once every chunk listed in its
+/// manifest has arrived or, for an erasure-coded file, every stripe has as
+/// many shards as it has chunks.
+fn try_complete_download(
+    downloads: &mut HashMap<String, DownloadState>,
+    file_name: &str,
+) -> DownloadStatus {
+    let Some(download_state) = downloads.get_mut(file_name) else {
+        return DownloadStatus::InProgress;
+    };
+    if download_state.assembling {
+        return DownloadStatus::InProgress;
+    }
+    let Some(manifest) = &download_state.manifest else {
+        return DownloadStatus::InProgress;
+    };
+    let have = |hash: &String| download_state.scheduler.is_received(hash);
+    let complete = match &manifest.erasure {
+        None => manifest.chunk_hashes.iter().all(have),
+        Some(erasure) => {
+            let total_chunks = manifest.total_chunks();
+            (0..erasure.stripe_count(total_chunks)).all(|stripe| {
+                let chunks = &manifest.chunk_hashes[erasure.stripe_chunks(stripe, total_chunks)];
+                let parity = &erasure.parity_hashes[erasure.stripe_parity(stripe)];
+                chunks.iter().chain(parity).filter(|hash| have(hash)).count() >= chunks.len()
+            })
+        }
+    };
+    if !complete {
+        return DownloadStatus::InProgress;
+    }
+    let received: HashSet<String> = manifest
+        .shard_hashes()
+        .into_iter()
+        .filter(|hash| download_state.scheduler.is_received(hash))
+        .collect();
+    let manifest = manifest.clone();
+    download_state.assembling = true;
+    DownloadStatus::Assemble(Box::new(manifest), received)
+}
+
+/// Rebuilds the missing chunks of a download from parity and reassembles it
+/// into `target` in a blocking task, so a large file does not hold up the
+/// event loop, and reports the outcome on `assembled`.
+fn start_assembly(
+    state: &NodeState,
+    file_name: &str,
+    manifest: FileManifest,
+    received: HashSet<String>,
+    target: PathBuf,
+) {
+    let store = state.chunk_store.clone();
+    let keys = state.keys.clone();
+    let assembled = state.assembled.clone();
+    let file_name = file_name.to_string();
+    tokio::task::spawn_blocking(move || {
+        let result = match rebuild_stripes(&received, &manifest, store.as_ref(), &keys) {
+            Ok(_) => assemble(&manifest, &target, store.as_ref(), &keys),
+            Err(NodeError::Integrity(reason)) => {
+                println!("{} Discarding download of '{}'.", reason, file_name);
+                Ok(DownloadStatus::Discarded(reason))
+            }
+            Err(e) => Err(e),
+        };
+        let _ = assembled.send(Assembled { file_name, result });
+    });
+}
+
+/// Reads the encrypted chunks of `manifest` back from the chunk store in
+/// order, checks them against their digests, decrypts them, checks them
+/// against the recorded sizes and appends them to a partial file, which
+/// replaces `target` once the manifest's Merkle root checks out.
+fn assemble(
+    manifest: &FileManifest,
+    target: &Path,
+    store: &dyn ChunkStore,
+    keys: &TopicKeys,
+) -> Result<DownloadStatus, NodeError> {
+    let file_name = &manifest.file_name;
+    let mut writer = ReassemblyWriter::create(target)?;
+    let mut hashes = Vec::with_capacity(manifest.chunk_hashes.len());
+    let mut corrupt = Vec::new();
+    for (chunk_hash, expected_size) in manifest.chunk_hashes.iter().zip(&manifest.chunk_sizes) {
+        let chunk_data = store.get(chunk_hash)?;
+        let actual_hash = chunker::hash_chunk(&chunk_data);
+        if actual_hash != *chunk_hash {
+            // Left behind by a failing disk or a store written by hand; the
+            // stored copy is useless, so drop it and fetch the chunk again.
+            if let Err(e) = store.delete(chunk_hash) {
+                println!("Failed to delete chunk '{}': {}", chunk_hash, e);
+            }
+            corrupt.push(chunk_hash.clone());
+            continue;
+        }
+        if !corrupt.is_empty() {
+            continue;
+        }
+        hashes.push(actual_hash);
+
+        match keys.decrypt_chunk(&chunk_data) {
+            Ok(plaintext) if plaintext.len() == *expected_size => writer.write_chunk(&plaintext)?,
+            _ => {
+                println!(
+                    "Could not decrypt '{}' with the topic key. Discarding download.",
+                    file_name
+                );
+                // Chunks are encrypted deterministically under the topic key,
+                // so one that fails here is useless to every file in the topic.
+                if let Err(e) = store.delete(chunk_hash) {
+                    println!("Failed to delete chunk '{}': {}", chunk_hash, e);
                 }
+                return Ok(DownloadStatus::Discarded(
+                    "file could not be decrypted with the topic key".to_string(),
+                ));
             }
         }
     }
 
-    if chunk_files.is_empty() {
-        None
-    } else {
-        chunk_files.sort_by_key(|f| {
-            if let Some((_, i, _)) = parse_chunk_filename(f) {
-                i
-            } else {
-                usize::MAX
+    if !corrupt.is_empty() {
+        return Ok(DownloadStatus::Refetch(corrupt));
+    }
+    if merkle_root(&hashes) != manifest.merkle_root {
+        println!(
+            "Reassembled '{}' does not match its manifest Merkle root. Discarding download.",
+            file_name
+        );
+        return Ok(DownloadStatus::Discarded(
+            "reassembled file does not match its Merkle root".to_string(),
+        ));
+    }
+
+    let output_path = writer.commit()?;
+    println!("Download complete: {}", output_path.display());
+    Ok(DownloadStatus::Complete(output_path))
+}
+
+/// Ends a download once its reassembly finished, unless it was cancelled
+/// in the meantime.
+fn finish_assembly(
+    assembled: Assembled,
+    swarm: &mut libp2p::Swarm<MyBehaviour>,
+    state: &mut NodeState,
+) {
+    let Assembled { file_name, result } = assembled;
+    let assembling = state
+        .downloads
+        .lock()
+        .unwrap()
+        .get(&file_name)
+        .is_some_and(|download_state| download_state.assembling);
+    if !assembling {
+        return;
+    }
+    match result {
+        Ok(DownloadStatus::Refetch(corrupt)) => {
+            println!(
+                "{} chunk(s) of '{}' were corrupt in the chunk store. Fetching them again.",
+                corrupt.len(),
+                file_name
+            );
+            if let Some(download_state) = state.downloads.lock().unwrap().get_mut(&file_name) {
+                download_state.assembling = false;
+                for chunk_hash in &corrupt {
+                    download_state.scheduler.requeue(chunk_hash);
+                }
             }
-        });
-        Some((total_chunks, chunk_files))
+        }
+        Ok(status) => {
+            state.downloads.lock().unwrap().remove(&file_name);
+            settle_download(swarm, state, &file_name, status);
+        }
+        Err(e) => fail_download(state, &file_name, format!("could not write the file: {}", e)),
+    }
+}
+
+/// Rebuilds the missing chunks of every stripe of an erasure-coded download
+/// that has as many shards as it has chunks, and stores them like fetched
+/// chunks. Returns the digests of the rebuilt chunks.
+fn rebuild_stripes(
+    received: &HashSet<String>,
+    manifest: &FileManifest,
+    store: &dyn ChunkStore,
+    keys: &TopicKeys,
+) -> Result<Vec<String>, NodeError> {
+    let Some(layout) = &manifest.erasure else {
+        return Ok(Vec::new());
+    };
+    let have = |hash: &String| received.contains(hash);
+    let total_chunks = manifest.total_chunks();
+    let mut rebuilt = Vec::new();
+    for stripe in 0..layout.stripe_count(total_chunks) {
+        let chunk_range = layout.stripe_chunks(stripe, total_chunks);
+        let chunks = &manifest.chunk_hashes[chunk_range.clone()];
+        let parity = &layout.parity_hashes[layout.stripe_parity(stripe)];
+        let missing = chunks.iter().filter(|hash| !have(hash)).count();
+        if missing == 0 || parity.iter().filter(|hash| have(hash)).count() < missing {
+            continue;
+        }
+
+        let chunk_data = chunks
+            .iter()
+            .map(|hash| have(hash).then(|| store.get(hash)).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut parity_data = Vec::with_capacity(parity.len());
+        for hash in parity {
+            if !have(hash) {
+                parity_data.push(None);
+                continue;
+            }
+            let shard = keys.decrypt_chunk(&store.get(hash)?).map_err(|_| {
+                NodeError::Integrity(format!(
+                    "Parity shard '{}' could not be decrypted with the topic key.",
+                    hash
+                ))
+            })?;
+            parity_data.push(Some(shard));
+        }
+        let stripe_chunks = erasure::reconstruct_stripe(layout, chunk_data, parity_data)
+            .map_err(|e| {
+                NodeError::Integrity(format!("Stripe {} could not be rebuilt: {}.", stripe + 1, e))
+            })?;
+
+        for ((mut data, hash), size) in stripe_chunks
+            .into_iter()
+            .zip(chunks)
+            .zip(&manifest.chunk_sizes[chunk_range])
+        {
+            if have(hash) {
+                continue;
+            }
+            data.truncate(crypto::encrypted_len(*size));
+            if chunker::hash_chunk(&data) != *hash {
+                return Err(NodeError::Integrity(format!(
+                    "Rebuilt chunk '{}' does not match its digest.",
+                    hash
+                )));
+            }
+            store.put(hash, &data)?;
+            println!("Rebuilt chunk '{}' of '{}' from parity", hash, manifest.file_name);
+            rebuilt.push(hash.clone());
+        }
+    }
+    Ok(rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunk_store::MemoryChunkStore;
+    use chunker::ChunkMetadata;
+    use command::ErasureCoding;
+    use upload_pipeline::PipelineEvent;
+
+    /// Uploads `data` through the upload pipeline, erasure coded as 3 chunks
+    /// and 2 parity shards per stripe, then drops the shards in `dropped`,
+    /// counted over the stripes in order, chunks before parity.
+    async fn erasure_coded_file(
+        keys: &TopicKeys,
+        store: &Arc<MemoryChunkStore>,
+        data: &[u8],
+        dropped: &[usize],
+    ) -> FileManifest {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("coded.bin");
+        fs::write(&path, data).unwrap();
+        let coding = ErasureCoding {
+            data_shards: 3,
+            parity_shards: 2,
+        };
+        let (events, mut pipeline_rx) = mpsc::channel(upload_pipeline::PIPELINE_QUEUE);
+        upload_pipeline::spawn(
+            0,
+            vec![path],
+            Chunking::Fixed,
+            Some(coding),
+            keys.clone(),
+            store.clone(),
+            events,
+        );
+        let chunked = loop {
+            if let PipelineEvent::Finished { result, .. } = pipeline_rx.recv().await.unwrap() {
+                break result.unwrap();
+            }
+        };
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let manifest =
+            FileManifest::new("coded.bin", &chunked.chunks, chunked.erasure, &keypair).unwrap();
+        for (i, hash) in manifest.stripes().concat().iter().enumerate() {
+            if dropped.contains(&i) {
+                store.delete(hash).unwrap();
+            }
+        }
+        manifest
+    }
+
+    fn file_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// The shards of `manifest` that `store` holds, as a download would have
+    /// recorded them.
+    fn received(manifest: &FileManifest, store: &dyn ChunkStore) -> HashSet<String> {
+        manifest
+            .shard_hashes()
+            .into_iter()
+            .filter(|hash| store.has(hash))
+            .collect()
+    }
+
+    #[test]
+    fn assemble_drops_corrupt_chunks_for_refetching() {
+        let keys = TopicKeys::derive("password", "assemble-test").unwrap();
+        let store = MemoryChunkStore::default();
+        let encrypted: Vec<Vec<u8>> = (0..3u8).map(|i| keys.encrypt_chunk(&[i; 100])).collect();
+        let chunks: Vec<ChunkMetadata> = encrypted
+            .iter()
+            .map(|data| ChunkMetadata {
+                size: 100,
+                hash: chunker::hash_chunk(data),
+            })
+            .collect();
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let manifest = FileManifest::new("plain.bin", &chunks, None, &keypair).unwrap();
+        store.put(&chunks[0].hash, &encrypted[0]).unwrap();
+        store.put(&chunks[1].hash, &encrypted[1][..50]).unwrap();
+        store.put(&chunks[2].hash, &encrypted[2]).unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let target = dir.path().join("plain.bin");
+        match assemble(&manifest, &target, &store, &keys).unwrap() {
+            DownloadStatus::Refetch(corrupt) => assert_eq!(corrupt, vec![chunks[1].hash.clone()]),
+            _ => panic!("corrupt chunk not reported"),
+        }
+        assert!(!store.has(&chunks[1].hash));
+        assert!(!target.exists());
+
+        store.put(&chunks[1].hash, &encrypted[1]).unwrap();
+        assert!(matches!(
+            assemble(&manifest, &target, &store, &keys).unwrap(),
+            DownloadStatus::Complete(_)
+        ));
+        assert_eq!(fs::read(&target).unwrap(), [[0; 100], [1; 100], [2; 100]].concat());
+    }
+
+    #[tokio::test]
+    async fn rebuild_stripes_restores_dropped_chunks() {
+        let keys = TopicKeys::derive("password", "erasure-test").unwrap();
+        let store = Arc::new(MemoryChunkStore::default());
+        // 8 fixed-size chunks, the last one short: stripes of chunks 0-2,
+        // 3-5 and a short one of 6-7, each followed by 2 parity shards. Up to
+        // 2 shards of each stripe are dropped: two chunks, a chunk and a
+        // parity shard, and both chunks of the short stripe.
+        let data = file_data(7 * 15 * 1024 + 500);
+        let dropped = [0, 2, 6, 9, 10, 11];
+        let manifest = erasure_coded_file(&keys, &store, &data, &dropped).await;
+        manifest.verify().unwrap();
+        assert_eq!(manifest.total_chunks(), 8);
+        let received = received(&manifest, store.as_ref());
+
+        let rebuilt = rebuild_stripes(&received, &manifest, store.as_ref(), &keys).unwrap();
+        let expected: Vec<usize> = vec![0, 2, 4, 6, 7];
+        assert_eq!(
+            rebuilt,
+            expected
+                .iter()
+                .map(|i| manifest.chunk_hashes[*i].clone())
+                .collect::<Vec<_>>()
+        );
+        let mut plaintext = Vec::new();
+        for hash in &manifest.chunk_hashes {
+            let chunk = store.get(hash).unwrap();
+            assert_eq!(chunker::hash_chunk(&chunk), *hash);
+            plaintext.extend(keys.decrypt_chunk(&chunk).unwrap());
+        }
+        assert_eq!(plaintext, data);
+    }
+
+    #[tokio::test]
+    async fn rebuild_stripes_skips_stripes_missing_too_many_shards() {
+        let keys = TopicKeys::derive("password", "erasure-test").unwrap();
+        let store = Arc::new(MemoryChunkStore::default());
+        // The first stripe loses three of its five shards.
+        let data = file_data(3 * 15 * 1024 + 100);
+        let manifest = erasure_coded_file(&keys, &store, &data, &[0, 1, 3]).await;
+        let received = received(&manifest, store.as_ref());
+        assert_eq!(
+            rebuild_stripes(&received, &manifest, store.as_ref(), &keys).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(!store.has(&manifest.chunk_hashes[0]));
     }
 }
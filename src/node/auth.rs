@@ -0,0 +1,149 @@
+use crate::node::protocol::{decode, encode, read_length_prefixed, write_length_prefixed};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use rand::RngCore;
+use std::io;
+
+const MAX_MESSAGE_SIZE: usize = 1024;
+pub const NONCE_SIZE: usize = 32;
+
+#[derive(Clone)]
+pub struct AuthProtocol();
+
+impl AsRef<str> for AuthProtocol {
+    fn as_ref(&self) -> &str {
+        "/p2p-sys/auth/2.0.0"
+    }
+}
+
+/// A fresh random challenge the responder must answer with an HMAC proof.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AuthChallenge {
+    pub nonce: Vec<u8>,
+}
+
+/// HMAC over the challenge nonce and both peer ids, keyed by the topic auth key.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AuthProof {
+    pub proof: Vec<u8>,
+}
+
+pub fn new_challenge() -> AuthChallenge {
+    let mut nonce = vec![0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    AuthChallenge { nonce }
+}
+
+#[derive(Clone, Default)]
+pub struct AuthCodec;
+
+#[async_trait]
+impl request_response::Codec for AuthCodec {
+    type Protocol = AuthProtocol;
+    type Request = AuthChallenge;
+    type Response = AuthProof;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        decode(&read_length_prefixed(io, MAX_MESSAGE_SIZE).await?)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        decode(&read_length_prefixed(io, MAX_MESSAGE_SIZE).await?)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: AuthChallenge,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, &encode(&request)?).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: AuthProof,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, &encode(&response)?).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use libp2p::request_response::Codec;
+
+    #[test]
+    fn challenges_and_proofs_round_trip() {
+        futures::executor::block_on(async {
+            let challenge = new_challenge();
+            let mut wire = Vec::new();
+            AuthCodec
+                .write_request(&AuthProtocol(), &mut Cursor::new(&mut wire), challenge.clone())
+                .await
+                .unwrap();
+            let read = AuthCodec
+                .read_request(&AuthProtocol(), &mut Cursor::new(&wire))
+                .await
+                .unwrap();
+            assert_eq!(read.nonce, challenge.nonce);
+
+            let proof = AuthProof { proof: vec![7; 32] };
+            let mut wire = Vec::new();
+            AuthCodec
+                .write_response(&AuthProtocol(), &mut Cursor::new(&mut wire), proof.clone())
+                .await
+                .unwrap();
+            let read = AuthCodec
+                .read_response(&AuthProtocol(), &mut Cursor::new(&wire))
+                .await
+                .unwrap();
+            assert_eq!(read.proof, proof.proof);
+        });
+    }
+
+    #[test]
+    fn oversized_challenges_are_refused() {
+        futures::executor::block_on(async {
+            let challenge = AuthChallenge {
+                nonce: vec![0; MAX_MESSAGE_SIZE],
+            };
+            let mut wire = Vec::new();
+            AuthCodec
+                .write_request(&AuthProtocol(), &mut Cursor::new(&mut wire), challenge)
+                .await
+                .unwrap();
+            let error = AuthCodec
+                .read_request(&AuthProtocol(), &mut Cursor::new(&wire))
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        });
+    }
+}
@@ -0,0 +1,269 @@
+use crate::node::chunker::ChunkMetadata;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+/// Storage backend for chunks, keyed by the hex-encoded SHA-256 digest of
+/// their bytes. Implementations do not verify digests; callers check them
+/// before `put`.
+pub trait ChunkStore: Send + Sync {
+    /// Stores a chunk. Storing a chunk that is already present is a no-op.
+    fn put(&self, hash: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Returns the bytes of a chunk, or an `ErrorKind::NotFound` error.
+    fn get(&self, hash: &str) -> io::Result<Vec<u8>>;
+
+    fn has(&self, hash: &str) -> bool;
+
+    /// Removes a chunk. Removing a missing chunk is not an error.
+    fn delete(&self, hash: &str) -> io::Result<()>;
+
+    /// Returns the digests of every stored chunk.
+    fn list(&self) -> io::Result<Vec<String>>;
+
+    /// Returns the size of a stored chunk without reading it.
+    fn stat(&self, hash: &str) -> io::Result<ChunkMetadata>;
+}
+
+/// Rejects keys that are not a SHA-256 digest, so a remote peer cannot make
+/// a store address anything outside its own chunks.
+fn check_hash(hash: &str) -> io::Result<()> {
+    if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid chunk digest: {:?}", hash),
+        ))
+    }
+}
+
+/// Stores each chunk as a file named by its digest in one directory.
+/// Chunks are written under a temporary name and renamed into place, so a
+/// crash never leaves a truncated chunk under a digest name.
+pub struct FsChunkStore {
+    dir: PathBuf,
+    next_tmp: AtomicU64,
+}
+
+impl FsChunkStore {
+    /// Opens the store in `dir`, removing chunks left half-written by a
+    /// crash.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(FsChunkStore {
+            dir,
+            next_tmp: AtomicU64::new(0),
+        })
+    }
+
+    fn path(&self, hash: &str) -> io::Result<PathBuf> {
+        check_hash(hash)?;
+        Ok(self.dir.join(hash))
+    }
+}
+
+impl ChunkStore for FsChunkStore {
+    fn put(&self, hash: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(hash)?;
+        if path.exists() {
+            return Ok(());
+        }
+        // Concurrent puts of one chunk each write their own temporary file.
+        let tmp_path = self.dir.join(format!(
+            "{}.{}.tmp",
+            hash,
+            self.next_tmp.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp_path, &path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(hash)?)
+    }
+
+    fn has(&self, hash: &str) -> bool {
+        self.path(hash).is_ok_and(|path| path.is_file())
+    }
+
+    fn delete(&self, hash: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(hash)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if check_hash(name).is_ok() && entry.file_type()?.is_file() {
+                    hashes.push(name.to_string());
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn stat(&self, hash: &str) -> io::Result<ChunkMetadata> {
+        let metadata = fs::metadata(self.path(hash)?)?;
+        Ok(ChunkMetadata {
+            size: metadata.len() as usize,
+            hash: hash.to_string(),
+        })
+    }
+}
+
+/// Keeps chunks in memory; they are lost when the node exits.
+#[derive(Default)]
+pub struct MemoryChunkStore {
+    chunks: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl ChunkStore for MemoryChunkStore {
+    fn put(&self, hash: &str, data: &[u8]) -> io::Result<()> {
+        check_hash(hash)?;
+        self.chunks
+            .write()
+            .unwrap()
+            .entry(hash.to_string())
+            .or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> io::Result<Vec<u8>> {
+        check_hash(hash)?;
+        self.chunks
+            .read()
+            .unwrap()
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| not_found(hash))
+    }
+
+    fn has(&self, hash: &str) -> bool {
+        self.chunks.read().unwrap().contains_key(hash)
+    }
+
+    fn delete(&self, hash: &str) -> io::Result<()> {
+        check_hash(hash)?;
+        self.chunks.write().unwrap().remove(hash);
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.chunks.read().unwrap().keys().cloned().collect())
+    }
+
+    fn stat(&self, hash: &str) -> io::Result<ChunkMetadata> {
+        check_hash(hash)?;
+        self.chunks
+            .read()
+            .unwrap()
+            .get(hash)
+            .map(|data| ChunkMetadata {
+                size: data.len(),
+                hash: hash.to_string(),
+            })
+            .ok_or_else(|| not_found(hash))
+    }
+}
+
+fn not_found(hash: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("chunk {} not found", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::chunker::hash_chunk;
+
+    fn check_contract(store: &dyn ChunkStore) {
+        let data = b"chunk bytes".to_vec();
+        let hash = hash_chunk(&data);
+        assert!(!store.has(&hash));
+        assert_eq!(store.get(&hash).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(store.stat(&hash).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        store.put(&hash, &data).unwrap();
+        // A second put of the same digest keeps the first bytes.
+        store.put(&hash, b"other bytes").unwrap();
+        assert!(store.has(&hash));
+        assert_eq!(store.get(&hash).unwrap(), data);
+        assert_eq!(store.stat(&hash).unwrap().size, data.len());
+        assert_eq!(store.list().unwrap(), vec![hash.clone()]);
+
+        store.delete(&hash).unwrap();
+        store.delete(&hash).unwrap();
+        assert!(!store.has(&hash));
+        assert!(store.list().unwrap().is_empty());
+
+        for invalid in ["../x", "x", &"g".repeat(64), &format!("../{}", &hash[3..])] {
+            assert!(!store.has(invalid));
+            for result in [
+                store.put(invalid, &data),
+                store.get(invalid).map(|_| ()),
+                store.delete(invalid),
+                store.stat(invalid).map(|_| ()),
+            ] {
+                assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", invalid);
+            }
+        }
+    }
+
+    #[test]
+    fn fs_store_keeps_the_contract() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::new(dir.path().join("chunks")).unwrap();
+        check_contract(&store);
+        assert!(!dir.path().join("x").exists());
+    }
+
+    #[test]
+    fn fs_store_lists_only_chunks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FsChunkStore::new(dir.path()).unwrap();
+        let hash = hash_chunk(b"data");
+        store.put(&hash, b"data").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"not a chunk").unwrap();
+        fs::create_dir(dir.path().join("a".repeat(64))).unwrap();
+        assert_eq!(store.list().unwrap(), vec![hash]);
+    }
+
+    #[test]
+    fn fs_store_drops_half_written_chunks_on_open() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let hash = hash_chunk(b"data");
+        fs::write(dir.path().join(format!("{}.0.tmp", hash)), b"da").unwrap();
+        let store = FsChunkStore::new(dir.path()).unwrap();
+        assert!(!store.has(&hash));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        store.put(&hash, b"data").unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn memory_store_keeps_the_contract() {
+        check_contract(&MemoryChunkStore::default());
+    }
+}
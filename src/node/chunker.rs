@@ -1,31 +1,205 @@
-use std::fs::File;
-use std::io::{self, Read};
+use crate::node::command::Chunking;
+use crate::node::{crypto, protocol};
+use sha2::{Digest, Sha256};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 const CHUNK_SIZE: usize =1024 * 15; // 15kb
+/// Bounds on content-defined chunk sizes. Below 64 bytes the rolling hash
+/// has not seen a full window. The 4 MiB cap keeps a chunk, once encrypted,
+/// well inside a single request or response of the file transfer protocol.
+pub const MIN_CHUNK_SIZE: usize = 64;
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Room left in a message for everything but the chunk bytes: the file
+/// name, the chunk hash and the encoding's own framing.
+const MESSAGE_HEADROOM: usize = 64 * 1024;
+
+const _: () = assert!(
+    crypto::encrypted_len(MAX_CHUNK_SIZE) + MESSAGE_HEADROOM <= protocol::MAX_MESSAGE_SIZE
+);
+
+/// Random values the rolling hash adds per byte. They fix where chunks are
+/// cut, so changing them changes the chunks of every file.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64 from a fixed seed.
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x7032_705f_7379_7321;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
 
 /// Represents metadata about a file chunk, including its hash.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChunkMetadata {
     pub size: usize,
+    /// Hex-encoded SHA-256 digest of the chunk bytes.
+    pub hash: String,
+}
+
+/// Returns the hex-encoded SHA-256 digest used to address a chunk.
+pub fn hash_chunk(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
 }
 
-pub fn chunk_file(file_path: &str) -> io::Result<Vec<ChunkMetadata>> {
-    let mut file = File::open(file_path)?;
-    let mut buffer = [0u8; CHUNK_SIZE];
-    let mut chunks_metadata = Vec::new();
+/// Splits a byte stream into chunks as `chunking` asks. At most one chunk
+/// of the largest size is held in memory.
+pub struct Chunker<R> {
+    reader: R,
+    chunking: Chunking,
+    buffer: Vec<u8>,
+}
 
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+impl<R: AsyncRead + Unpin> Chunker<R> {
+    pub fn new(reader: R, chunking: Chunking) -> Self {
+        Chunker {
+            reader,
+            chunking,
+            buffer: Vec::new(),
         }
+    }
+
+    /// The largest chunk [`Chunker::next_chunk`] returns.
+    pub fn max_chunk_size(&self) -> usize {
+        match self.chunking {
+            Chunking::Fixed => CHUNK_SIZE,
+            Chunking::ContentDefined { max_size, .. } => max_size,
+        }
+    }
 
-        let metadata = ChunkMetadata {
-            size: bytes_read,
+    /// Reads the next chunk, or `None` at the end of the stream.
+    pub async fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let max_size = self.max_chunk_size();
+        if self.buffer.len() < max_size {
+            (&mut self.reader)
+                .take((max_size - self.buffer.len()) as u64)
+                .read_to_end(&mut self.buffer)
+                .await?;
+        }
+        if self.buffer.is_empty() {
+            return Ok(None);
+        }
+        let cut = match self.chunking {
+            Chunking::Fixed => self.buffer.len(),
+            Chunking::ContentDefined {
+                min_size,
+                avg_size,
+                max_size,
+            } => cut_point(&self.buffer, min_size, avg_size, max_size),
         };
+        let rest = self.buffer.split_off(cut);
+        Ok(Some(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
 
-        chunks_metadata.push(metadata);
+/// Finds the end of the first chunk of `data` with FastCDC: a gear hash
+/// rolls over the bytes past `min_size`, and the chunk ends where its top
+/// bits are all zero. More bits must be zero before `avg_size` than after,
+/// which keeps most chunks close to the average size.
+fn cut_point(data: &[u8], min_size: usize, avg_size: usize, max_size: usize) -> usize {
+    if data.len() <= min_size {
+        return data.len();
     }
+    let end = data.len().min(max_size);
+    let normal = end.min(avg_size);
+    let bits = avg_size.ilog2();
+    let mask_small = !(u64::MAX >> (bits + 2));
+    let mask_large = !(u64::MAX >> (bits - 2));
 
-    Ok(chunks_metadata)
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(end).skip(min_size) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < normal { mask_small } else { mask_large };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Bytes from a xorshift generator, so the tests see the same data on
+    /// every run.
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    async fn chunks(data: &[u8], chunking: Chunking) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(data, chunking);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = chunker.next_chunk().await.unwrap() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn fixed_chunking_cuts_15kb_chunks() {
+        let data = pseudo_random(CHUNK_SIZE * 4 + 100, 1);
+        let chunks = chunks(&data, Chunking::Fixed).await;
+        let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE, 100]);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn content_defined_chunks_stay_within_bounds() {
+        let Chunking::ContentDefined {
+            min_size, max_size, ..
+        } = Chunking::CONTENT_DEFINED
+        else {
+            unreachable!();
+        };
+        let data = pseudo_random(4 * 1024 * 1024, 2);
+        let chunks = chunks(&data, Chunking::CONTENT_DEFINED).await;
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest
+            .iter()
+            .all(|chunk| (min_size..=max_size).contains(&chunk.len())));
+        assert!(last.len() <= max_size);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn inserting_a_byte_keeps_most_content_defined_chunks() {
+        let data = pseudo_random(2 * 1024 * 1024, 3);
+        let mut edited = data.clone();
+        edited.insert(100, 0x42);
+
+        let before: Vec<String> = chunks(&data, Chunking::CONTENT_DEFINED)
+            .await
+            .iter()
+            .map(|chunk| hash_chunk(chunk))
+            .collect();
+        let after: HashSet<String> = chunks(&edited, Chunking::CONTENT_DEFINED)
+            .await
+            .iter()
+            .map(|chunk| hash_chunk(chunk))
+            .collect();
+        let kept = before.iter().filter(|hash| after.contains(*hash)).count();
+        assert!(before.len() > 50);
+        assert!(kept >= before.len() - 2, "kept {} of {}", kept, before.len());
+    }
 }
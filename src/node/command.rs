@@ -0,0 +1,240 @@
+use crate::node::error::NodeError;
+use crate::node::FileTransferLog;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::PathBuf;
+use tokio::sync::oneshot;
+
+/// A request to a running node, answered on the enclosed channel.
+pub(crate) enum NodeCommand {
+    /// Upload every file named by `paths` (files, directories or globs) with
+    /// the settings of `options`, the node's defaults filling in the rest.
+    /// Answered once every replica has acknowledged or failed.
+    Put {
+        paths: Vec<String>,
+        options: PutOptions,
+        reply: oneshot::Sender<Result<Vec<PutReport>, NodeError>>,
+    },
+    /// Download `file_name`, copying it to `output` when given.
+    /// Answered with the path of the reassembled file.
+    Get {
+        file_name: String,
+        output: Option<PathBuf>,
+        reply: oneshot::Sender<Result<PathBuf, NodeError>>,
+    },
+    /// List the downloads in progress, including ones resumed after a restart.
+    Downloads {
+        reply: oneshot::Sender<Vec<DownloadInfo>>,
+    },
+    /// Stop downloading `file_name`, or forget it if it failed. Chunks
+    /// fetched so far stay in the chunk store.
+    CancelDownload {
+        file_name: String,
+        reply: oneshot::Sender<Result<(), NodeError>>,
+    },
+    /// List the files this node holds a manifest for.
+    List {
+        reply: oneshot::Sender<Result<Vec<FileEntry>, NodeError>>,
+    },
+    /// Change how many peers keep each chunk of an uploaded file.
+    SetReplication {
+        file_name: String,
+        replication_factor: usize,
+        reply: oneshot::Sender<Result<(), NodeError>>,
+    },
+    /// List the authenticated peers.
+    Peers {
+        reply: oneshot::Sender<Vec<PeerInfo>>,
+    },
+    /// Return the peer score table.
+    Scores {
+        reply: oneshot::Sender<Vec<PeerScore>>,
+    },
+    /// Return the file transfer logs matching `query`.
+    Logs {
+        query: LogQuery,
+        reply: oneshot::Sender<Vec<FileTransferLog>>,
+    },
+    /// Publish a chat message to the topic.
+    Publish {
+        text: String,
+        reply: oneshot::Sender<Result<(), NodeError>>,
+    },
+}
+
+/// Settings of one upload; unset fields take the node's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PutOptions {
+    pub redundancy: Option<Redundancy>,
+    pub chunking: Option<Chunking>,
+}
+
+/// How an upload protects its chunks against peers leaving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redundancy {
+    /// Full copies of every chunk on this many peers.
+    Replication(usize),
+    /// Parity shards computed over stripes of chunks, each shard on one peer.
+    ErasureCoding(ErasureCoding),
+}
+
+/// Stripes of `data_shards` chunks plus `parity_shards` parity shards. Any
+/// `data_shards` shards of a stripe rebuild it, so a stripe survives losing
+/// `parity_shards` peers while storing `(data_shards + parity_shards) /
+/// data_shards` times the file size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ErasureCoding {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+}
+
+impl std::str::FromStr for ErasureCoding {
+    type Err = String;
+
+    /// Parses `<data>+<parity>`, e.g. `4+2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (data, parity) = s
+            .split_once('+')
+            .ok_or_else(|| "expected <data shards>+<parity shards>, e.g. 4+2".to_string())?;
+        let parse = |n: &str| n.trim().parse::<usize>().map_err(|e| e.to_string());
+        Ok(ErasureCoding {
+            data_shards: parse(data)?,
+            parity_shards: parse(parity)?,
+        })
+    }
+}
+
+/// How uploaded files are split into chunks. Chunks are addressed by the
+/// digest of their content, so files sharing chunks store and send them
+/// once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum Chunking {
+    /// 15 KB chunks. Inserting or removing a byte shifts every later chunk.
+    #[default]
+    Fixed,
+    /// FastCDC chunks cut where a rolling hash of the content matches, so
+    /// an edit only changes the chunks around it. Sizes are in bytes.
+    ContentDefined {
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    },
+}
+
+impl Chunking {
+    /// Content-defined chunks of 4 to 64 KiB, 16 KiB on average.
+    pub const CONTENT_DEFINED: Chunking = Chunking::ContentDefined {
+        min_size: 4 * 1024,
+        avg_size: 16 * 1024,
+        max_size: 64 * 1024,
+    };
+}
+
+impl std::str::FromStr for Chunking {
+    type Err = String;
+
+    /// Parses `fixed`, `cdc`, or `cdc:<min>:<avg>:<max>` with sizes in bytes,
+    /// e.g. `cdc:2048:8192:32768`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| n.trim().parse::<usize>().map_err(|e| e.to_string());
+        match s.split(':').collect::<Vec<_>>().as_slice() {
+            ["fixed"] => Ok(Chunking::Fixed),
+            ["cdc"] => Ok(Chunking::CONTENT_DEFINED),
+            ["cdc", min, avg, max] => Ok(Chunking::ContentDefined {
+                min_size: parse(min)?,
+                avg_size: parse(avg)?,
+                max_size: parse(max)?,
+            }),
+            _ => Err("expected fixed, cdc or cdc:<min>:<avg>:<max>".to_string()),
+        }
+    }
+}
+
+/// How far an uploaded file got replicated.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PutReport {
+    pub file_name: String,
+    /// Chunks of the file, plus its parity shards when erasure coded.
+    pub total_chunks: usize,
+    /// Chunks or shards at least one peer confirmed storing.
+    pub replicated_chunks: usize,
+}
+
+impl PutReport {
+    pub fn is_complete(&self) -> bool {
+        self.total_chunks > 0 && self.replicated_chunks == self.total_chunks
+    }
+}
+
+/// A file known to this node through its stored manifest.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileEntry {
+    pub file_name: String,
+    pub total_length: u64,
+    pub total_chunks: usize,
+    /// Chunks of the file held in the local chunk store.
+    pub local_chunks: usize,
+    pub publisher: String,
+    /// Set for files uploaded from this node.
+    #[serde(default)]
+    pub replication: Option<ReplicationStatus>,
+}
+
+/// A download in progress, or one that failed recently.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DownloadInfo {
+    pub file_name: String,
+    pub started_at: DateTime<Utc>,
+    pub received_chunks: usize,
+    /// Absent until a peer sent the manifest.
+    pub total_chunks: Option<usize>,
+    pub in_flight: usize,
+    #[serde(flatten)]
+    pub state: DownloadPhase,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DownloadPhase {
+    Downloading,
+    /// The download gave up, e.g. at its deadline or when no peer could
+    /// serve some chunks.
+    Failed {
+        reason: String,
+        /// Indices of the chunks that never arrived.
+        missing_chunks: Vec<usize>,
+    },
+}
+
+/// How well the chunks of an uploaded file are replicated.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReplicationStatus {
+    pub replication_factor: usize,
+    /// Connected peers holding the least replicated chunk.
+    pub live_replicas: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub connected: bool,
+    /// False while the peer is reached only through a relay.
+    pub direct: bool,
+    /// Absent while the peer is out of the score table, e.g. when disconnected.
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PeerScore {
+    pub peer_id: String,
+    pub score: f64,
+}
+
+/// Filters for the file transfer logs; unset fields match everything.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct LogQuery {
+    pub peer_id: Option<String>,
+    /// Matches file names containing this text.
+    pub file_name: Option<String>,
+    pub date: Option<NaiveDate>,
+}
@@ -0,0 +1,53 @@
+use crate::node::error::NodeError;
+use libp2p::Multiaddr;
+use std::fs;
+use std::path::Path;
+
+/// Name of the config file looked up in the data directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Addresses read from a TOML config file, e.g.
+///
+/// ```toml
+/// listen = ["/ip4/0.0.0.0/tcp/4001"]
+/// peers = ["/dns4/storage-1/tcp/4001/p2p/12D3KooW..."]
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    pub listen: Vec<Multiaddr>,
+    /// Peers to keep connected to, redialed when they drop.
+    pub peers: Vec<Multiaddr>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfigFile {
+    #[serde(default)]
+    listen: Vec<String>,
+    #[serde(default)]
+    peers: Vec<String>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, NodeError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| NodeError::Config(format!("{}: {}", path.display(), e)))?;
+        let raw: RawConfigFile = toml::from_str(&text)
+            .map_err(|e| NodeError::Config(format!("{}: {}", path.display(), e)))?;
+        Ok(ConfigFile {
+            listen: parse_addresses(path, raw.listen)?,
+            peers: parse_addresses(path, raw.peers)?,
+        })
+    }
+}
+
+fn parse_addresses(path: &Path, addresses: Vec<String>) -> Result<Vec<Multiaddr>, NodeError> {
+    addresses
+        .iter()
+        .map(|address| {
+            address.parse().map_err(|e| {
+                NodeError::Config(format!("{}: invalid address '{}': {}", path.display(), address, e))
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,232 @@
+use crate::node::command::{
+    Chunking, DownloadInfo, ErasureCoding, FileEntry, LogQuery, PeerInfo, PeerScore, PutOptions,
+    PutReport, Redundancy,
+};
+use crate::node::{FileTransferLog, NodeHandle};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Upper bound on a single control message.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const SOCKET_FILE: &str = "control.sock";
+
+/// A request sent to a running node over its control socket. Each message
+/// is JSON, prefixed with its length as a big-endian `u32`, e.g.
+/// `{"command":"download","file_name":"notes.txt","output":null}`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Upload {
+        paths: Vec<String>,
+        /// Replicas per chunk; the node's default when absent.
+        #[serde(default)]
+        replicas: Option<usize>,
+        /// Erasure code the files instead of replicating them, e.g.
+        /// `{"data_shards":4,"parity_shards":2}`.
+        #[serde(default)]
+        erasure: Option<ErasureCoding>,
+        /// How to split the files, e.g. `{"scheme":"content_defined","min_size":4096,
+        /// "avg_size":16384,"max_size":65536}`; the node's default when absent.
+        #[serde(default)]
+        chunking: Option<Chunking>,
+    },
+    Download {
+        file_name: String,
+        output: Option<PathBuf>,
+    },
+    /// List the downloads in progress.
+    Downloads,
+    /// Stop an in-progress download.
+    CancelDownload { file_name: String },
+    /// Change the replica count of a file uploaded from the node.
+    Replicate { file_name: String, replicas: usize },
+    List,
+    Peers,
+    Scores,
+    Logs {
+        #[serde(default, flatten)]
+        query: LogQuery,
+    },
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Uploaded { files: Vec<PutReport> },
+    Downloaded { path: PathBuf },
+    Downloads { downloads: Vec<DownloadInfo> },
+    Cancelled { file_name: String },
+    Replicated { file_name: String, replicas: usize },
+    Files { files: Vec<FileEntry> },
+    Peers { peers: Vec<PeerInfo> },
+    Scores { scores: Vec<PeerScore> },
+    Logs { logs: Vec<FileTransferLog> },
+    Error { message: String },
+}
+
+/// Where a node with data directory `data_dir` listens for control requests.
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SOCKET_FILE)
+}
+
+/// Runs `request` against the node behind `node` and waits for the answer.
+pub async fn execute(request: ControlRequest, node: &NodeHandle) -> ControlResponse {
+    let response = match request {
+        ControlRequest::Upload {
+            paths,
+            replicas,
+            erasure,
+            chunking,
+        } => {
+            let redundancy = match (replicas, erasure) {
+                (_, Some(coding)) => Some(Redundancy::ErasureCoding(coding)),
+                (Some(replicas), None) => Some(Redundancy::Replication(replicas)),
+                (None, None) => None,
+            };
+            node.put_with(
+                paths,
+                PutOptions {
+                    redundancy,
+                    chunking,
+                },
+            )
+            .await
+            .map(|files| ControlResponse::Uploaded { files })
+        }
+        ControlRequest::Download { file_name, output } => node
+            .get_file(&file_name, output)
+            .await
+            .map(|path| ControlResponse::Downloaded { path }),
+        ControlRequest::Downloads => node
+            .downloads()
+            .await
+            .map(|downloads| ControlResponse::Downloads { downloads }),
+        ControlRequest::CancelDownload { file_name } => node
+            .cancel_download(&file_name)
+            .await
+            .map(|()| ControlResponse::Cancelled { file_name }),
+        ControlRequest::Replicate {
+            file_name,
+            replicas,
+        } => node
+            .set_replication_factor(&file_name, replicas)
+            .await
+            .map(|()| ControlResponse::Replicated {
+                file_name,
+                replicas,
+            }),
+        ControlRequest::List => node
+            .list_files()
+            .await
+            .map(|files| ControlResponse::Files { files }),
+        ControlRequest::Peers => node
+            .peers()
+            .await
+            .map(|peers| ControlResponse::Peers { peers }),
+        ControlRequest::Scores => node
+            .scores()
+            .await
+            .map(|scores| ControlResponse::Scores { scores }),
+        ControlRequest::Logs { query } => node
+            .logs(query)
+            .await
+            .map(|logs| ControlResponse::Logs { logs }),
+    };
+    response.unwrap_or_else(|e| ControlResponse::Error {
+        message: e.to_string(),
+    })
+}
+
+/// Binds the control socket at `path`. A socket file left behind by a node
+/// that is no longer running is replaced; a live one means another node
+/// already uses this data directory, which is an error.
+pub async fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another node is listening on {}", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    restrict_to_owner(path)?;
+    println!("Control socket listening on {}", path.display());
+    Ok(listener)
+}
+
+/// Accepts control connections and forwards their requests to `node`.
+pub async fn serve(listener: UnixListener, node: NodeHandle) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, node).await {
+                println!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: UnixStream, node: NodeHandle) -> io::Result<()> {
+    loop {
+        let data = match read_frame(&mut stream).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let response = match serde_json::from_slice(&data) {
+            Ok(request) => execute(request, &node).await,
+            Err(e) => ControlResponse::Error {
+                message: format!("invalid request: {}", e),
+            },
+        };
+        write_frame(&mut stream, &serde_json::to_vec(&response)?).await?;
+    }
+}
+
+/// Connects to the control socket of a running node.
+pub async fn connect(path: &Path) -> io::Result<UnixStream> {
+    UnixStream::connect(path).await
+}
+
+/// Sends one request over a control connection and reads the response.
+pub async fn request(
+    stream: &mut UnixStream,
+    request: &ControlRequest,
+) -> io::Result<ControlResponse> {
+    write_frame(stream, &serde_json::to_vec(request)?).await?;
+    let data = read_frame(stream).await?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn read_frame<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Vec<u8>> {
+    let len = io.read_u32().await? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds limit of {} bytes", len, MAX_MESSAGE_SIZE),
+        ));
+    }
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+async fn write_frame<T: AsyncWrite + Unpin>(io: &mut T, data: &[u8]) -> io::Result<()> {
+    io.write_u32(data.len() as u32).await?;
+    io.write_all(data).await?;
+    io.flush().await
+}
+
+/// Only the owner of the data directory may drive the node.
+fn restrict_to_owner(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+}
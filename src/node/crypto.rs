@@ -0,0 +1,254 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// Length of a chunk of `plaintext_len` bytes once encrypted.
+pub const fn encrypted_len(plaintext_len: usize) -> usize {
+    NONCE_SIZE + plaintext_len + TAG_SIZE
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    KeyDerivation(String),
+    Malformed,
+    Decryption,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::KeyDerivation(e) => write!(f, "key derivation failed: {}", e),
+            CryptoError::Malformed => write!(f, "ciphertext is too short"),
+            CryptoError::Decryption => write!(f, "decryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Symmetric keys shared by every peer that knows the topic password.
+/// The password itself is only used to derive these keys and never leaves the node.
+#[derive(Clone)]
+pub struct TopicKeys {
+    chunk_key: [u8; 32],
+    chunk_nonce_key: [u8; 32],
+    message_key: [u8; 32],
+    auth_key: [u8; 32],
+    content_key: [u8; 32],
+}
+
+impl TopicKeys {
+    /// Stretches the password with Argon2id, salted with the topic name, and
+    /// expands the result into independent subkeys with HKDF-SHA256.
+    pub fn derive(password: &str, topic: &str) -> Result<Self, CryptoError> {
+        let salt = Sha256::digest(format!("p2p_sys/topic/{}", topic));
+        let mut master = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(password.as_bytes(), &salt, &mut master)
+            .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+
+        let hkdf = Hkdf::<Sha256>::new(None, &master);
+        let expand = |info: &[u8]| -> Result<[u8; 32], CryptoError> {
+            let mut key = [0u8; 32];
+            hkdf.expand(info, &mut key)
+                .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+            Ok(key)
+        };
+
+        Ok(TopicKeys {
+            chunk_key: expand(b"p2p_sys chunk encryption")?,
+            chunk_nonce_key: expand(b"p2p_sys chunk nonce")?,
+            message_key: expand(b"p2p_sys message encryption")?,
+            auth_key: expand(b"p2p_sys peer authentication")?,
+            content_key: expand(b"p2p_sys content addressing")?,
+        })
+    }
+
+    /// Encrypts a chunk as `nonce || ciphertext`. The nonce is derived from the
+    /// plaintext, so identical chunks encrypt to identical bytes and keep the
+    /// same content address.
+    pub fn encrypt_chunk(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce_bytes = self.chunk_nonce(plaintext);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.chunk_key));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+            .expect("chunk encryption cannot fail");
+        [nonce_bytes.as_slice(), &ciphertext].concat()
+    }
+
+    pub fn decrypt_chunk(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let plaintext = open(&self.chunk_key, data)?;
+        if self.chunk_nonce(&plaintext)[..] != data[..NONCE_SIZE] {
+            return Err(CryptoError::Decryption);
+        }
+        Ok(plaintext)
+    }
+
+    /// Encrypts a gossipsub payload as `nonce || ciphertext` with a random nonce.
+    pub fn seal_message(&self, plaintext: &[u8]) -> Vec<u8> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.message_key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .expect("message encryption cannot fail");
+        [nonce.as_slice(), &ciphertext].concat()
+    }
+
+    pub fn open_message(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        open(&self.message_key, data)
+    }
+
+    /// Proves knowledge of the topic password for a handshake challenge.
+    /// The proof binds the challenge nonce to both peers, so it cannot be
+    /// replayed for another challenge or reflected back to its sender.
+    pub fn auth_proof(&self, nonce: &[u8], prover: &PeerId, verifier: &PeerId) -> Vec<u8> {
+        self.auth_mac(nonce, prover, verifier)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    /// Keyed digest naming `content` in the DHT without revealing it.
+    pub fn content_key(&self, content: &str) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.content_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(content.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    pub fn verify_auth_proof(
+        &self,
+        nonce: &[u8],
+        prover: &PeerId,
+        verifier: &PeerId,
+        proof: &[u8],
+    ) -> bool {
+        self.auth_mac(nonce, prover, verifier)
+            .verify_slice(proof)
+            .is_ok()
+    }
+
+    fn auth_mac(&self, nonce: &[u8], prover: &PeerId, verifier: &PeerId) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.auth_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"p2p_sys auth v1");
+        mac.update(nonce);
+        mac.update(&prover.to_bytes());
+        mac.update(&verifier.to_bytes());
+        mac
+    }
+
+    fn chunk_nonce(&self, plaintext: &[u8]) -> [u8; NONCE_SIZE] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.chunk_nonce_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(plaintext);
+        let tag = mac.finalize().into_bytes();
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&tag[..NONCE_SIZE]);
+        nonce
+    }
+}
+
+fn open(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    if data.len() < NONCE_SIZE {
+        return Err(CryptoError::Malformed);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError::Decryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> TopicKeys {
+        TopicKeys::derive("password", "crypto-test").unwrap()
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let keys = keys();
+        let encrypted = keys.encrypt_chunk(b"chunk bytes");
+        assert_eq!(encrypted.len(), encrypted_len(11));
+        assert_eq!(keys.decrypt_chunk(&encrypted).unwrap(), b"chunk bytes");
+        assert_eq!(keys.decrypt_chunk(&keys.encrypt_chunk(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn equal_chunks_encrypt_to_equal_bytes() {
+        let keys = keys();
+        assert_eq!(keys.encrypt_chunk(b"same"), keys.encrypt_chunk(b"same"));
+        assert_ne!(keys.encrypt_chunk(b"same"), keys.encrypt_chunk(b"other"));
+    }
+
+    #[test]
+    fn tampered_chunks_are_rejected() {
+        let keys = keys();
+        let encrypted = keys.encrypt_chunk(b"chunk bytes");
+        for i in [0, NONCE_SIZE - 1, NONCE_SIZE, encrypted.len() - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[i] ^= 1;
+            assert!(matches!(keys.decrypt_chunk(&tampered), Err(CryptoError::Decryption)), "{}", i);
+        }
+        assert!(matches!(keys.decrypt_chunk(&encrypted[..4]), Err(CryptoError::Malformed)));
+
+        // A chunk sealed under a nonce other than the one its plaintext
+        // derives is rejected even though it decrypts.
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&keys.chunk_key));
+        let nonce = [7u8; NONCE_SIZE];
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), b"chunk bytes".as_slice())
+            .unwrap();
+        let forged = [nonce.as_slice(), &ciphertext].concat();
+        assert!(matches!(keys.decrypt_chunk(&forged), Err(CryptoError::Decryption)));
+    }
+
+    #[test]
+    fn messages_round_trip_with_random_nonces() {
+        let keys = keys();
+        let first = keys.seal_message(b"hello");
+        let second = keys.seal_message(b"hello");
+        assert_ne!(first[..NONCE_SIZE], second[..NONCE_SIZE]);
+        assert_eq!(keys.open_message(&first).unwrap(), b"hello");
+        assert_eq!(keys.open_message(&second).unwrap(), b"hello");
+        // Chunk and message keys are independent.
+        assert!(keys.decrypt_chunk(&first).is_err());
+    }
+
+    #[test]
+    fn other_passwords_cannot_decrypt() {
+        let keys = keys();
+        let other = TopicKeys::derive("wrong password", "crypto-test").unwrap();
+        assert!(other.decrypt_chunk(&keys.encrypt_chunk(b"chunk")).is_err());
+        assert!(other.open_message(&keys.seal_message(b"message")).is_err());
+        assert_ne!(other.content_key("notes.txt"), keys.content_key("notes.txt"));
+    }
+
+    #[test]
+    fn auth_proofs_bind_both_peers() {
+        let keys = keys();
+        let [prover, verifier] = [PeerId::random(), PeerId::random()];
+        let proof = keys.auth_proof(b"nonce", &prover, &verifier);
+        assert!(keys.verify_auth_proof(b"nonce", &prover, &verifier, &proof));
+        assert!(!keys.verify_auth_proof(b"nonce", &verifier, &prover, &proof));
+        assert!(!keys.verify_auth_proof(b"other nonce", &prover, &verifier, &proof));
+
+        let other = TopicKeys::derive("wrong password", "crypto-test").unwrap();
+        assert!(!keys.verify_auth_proof(
+            b"nonce",
+            &prover,
+            &verifier,
+            &other.auth_proof(b"nonce", &prover, &verifier)
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::node::crypto::TopicKeys;
+use libp2p::kad::{self, store::MemoryStore, store::MemoryStoreConfig};
+use libp2p::{PeerId, StreamProtocol};
+use std::time::Duration;
+
+/// Kademlia protocol of this application, kept apart from the public IPFS DHT.
+pub const KAD_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-sys/kad/1.0.0");
+pub const IDENTIFY_PROTOCOL: &str = "/p2p-sys/id/1.0.0";
+/// Every chunk held is announced, so the default limit of 1024 keys is too low.
+const MAX_PROVIDED_KEYS: usize = 1 << 20;
+/// How often the routing table is refreshed by looking up random keys
+/// through the peers already known.
+pub const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// What a running provider lookup is for.
+pub enum ProviderQuery {
+    /// Holders of the manifest of `file_name`; `found` counts the providers seen so far.
+    Manifest { file_name: String, found: usize },
+    /// Holders of one chunk of `file_name`.
+    Chunk { file_name: String, chunk_hash: String },
+}
+
+pub fn new_kademlia(local_peer_id: PeerId) -> kad::Behaviour<MemoryStore> {
+    let store = MemoryStore::with_config(
+        local_peer_id,
+        MemoryStoreConfig {
+            max_provided_keys: MAX_PROVIDED_KEYS,
+            ..Default::default()
+        },
+    );
+    let mut config = kad::Config::new(KAD_PROTOCOL);
+    config.set_query_timeout(Duration::from_secs(30));
+    // The node bootstraps on its own schedule, see `bootstrap`.
+    config.set_periodic_bootstrap_interval(None);
+    let mut kademlia = kad::Behaviour::with_config(local_peer_id, store, config);
+    // Peers on a LAN never confirm an external address, so answer DHT
+    // requests regardless instead of waiting to become a server.
+    kademlia.set_mode(Some(kad::Mode::Server));
+    kademlia
+}
+
+/// Fills the routing table with peers beyond the direct neighbours by
+/// looking up this node's own id and random keys. Does nothing while no
+/// peer is known yet.
+pub fn bootstrap(kademlia: &mut kad::Behaviour<MemoryStore>) -> bool {
+    kademlia.bootstrap().is_ok()
+}
+
+/// DHT key under which holders of the manifest of `file_name` are announced.
+/// Keys are keyed hashes, so peers outside the topic cannot tell which
+/// files or chunks a provider holds.
+pub fn manifest_key(keys: &TopicKeys, file_name: &str) -> kad::RecordKey {
+    kad::RecordKey::new(&keys.content_key(&format!("manifest/{}", file_name)))
+}
+
+/// DHT key under which holders of the chunk `chunk_hash` are announced.
+pub fn chunk_key(keys: &TopicKeys, chunk_hash: &str) -> kad::RecordKey {
+    kad::RecordKey::new(&keys.content_key(&format!("chunk/{}", chunk_hash)))
+}
+
+/// Announces this node as a provider of `key`.
+pub fn provide(kademlia: &mut kad::Behaviour<MemoryStore>, key: kad::RecordKey) {
+    if let Err(e) = kademlia.start_providing(key) {
+        println!("Failed to announce provider record: {}", e);
+    }
+}
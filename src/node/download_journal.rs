@@ -0,0 +1,115 @@
+use crate::node::storage_manager::{self, StorageLayout};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// An in-flight download as recorded on disk.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    pub started_at: DateTime<Utc>,
+}
+
+/// The downloads this node has not finished, persisted in
+/// `<data-dir>/downloads.json` so a restarted node resumes them. Fetched
+/// chunks go to the chunk store as they arrive, so the journal only needs
+/// the file names; the manifest and the held chunks tell what is missing.
+pub struct DownloadJournal {
+    layout: StorageLayout,
+    entries: BTreeMap<String, JournalEntry>,
+}
+
+impl DownloadJournal {
+    /// Loads the journal. One that cannot be parsed is reported and
+    /// started afresh; the downloads it listed are not resumed.
+    pub fn load(layout: &StorageLayout) -> io::Result<Self> {
+        let entries = match fs::read(layout.download_journal_path()) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                println!("Ignoring unreadable download journal: {}", e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(DownloadJournal {
+            layout: layout.clone(),
+            entries,
+        })
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(io::Error::from)
+            .and_then(|bytes| storage_manager::write_atomic(&self.layout.download_journal_path(), &bytes));
+        if let Err(e) = result {
+            println!("Failed to save download journal: {}", e);
+        }
+    }
+
+    /// Records a download as started, keeping the start time of one already
+    /// recorded.
+    pub fn start(&mut self, file_name: &str) {
+        if !self.entries.contains_key(file_name) {
+            self.entries.insert(
+                file_name.to_string(),
+                JournalEntry {
+                    started_at: Utc::now(),
+                },
+            );
+            self.save();
+        }
+    }
+
+    /// Forgets a download that completed, failed or was cancelled.
+    pub fn finish(&mut self, file_name: &str) {
+        if self.entries.remove(file_name).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<&JournalEntry> {
+        self.entries.get(file_name)
+    }
+
+    pub fn file_names(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_survive_a_reload() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let layout = StorageLayout::new(dir.path());
+        let mut journal = DownloadJournal::load(&layout).unwrap();
+        journal.start("a.bin");
+        journal.start("b.bin");
+        journal.finish("a.bin");
+
+        let reloaded = DownloadJournal::load(&layout).unwrap();
+        assert_eq!(reloaded.file_names(), vec!["b.bin".to_string()]);
+        assert_eq!(
+            reloaded.get("b.bin").unwrap().started_at,
+            journal.get("b.bin").unwrap().started_at
+        );
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["downloads.json"]);
+    }
+
+    #[test]
+    fn truncated_journal_starts_empty() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let layout = StorageLayout::new(dir.path());
+        fs::write(layout.download_journal_path(), br#"{"a.bin":{"started_at":"20"#).unwrap();
+        let mut journal = DownloadJournal::load(&layout).unwrap();
+        assert!(journal.file_names().is_empty());
+        journal.start("b.bin");
+        assert_eq!(DownloadJournal::load(&layout).unwrap().file_names(), vec!["b.bin".to_string()]);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::node::manifest::ErasureLayout;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+/// Shards per stripe the GF(2^8) code supports.
+pub const MAX_SHARDS: usize = 256;
+
+/// Computes the parity shards of one stripe of encrypted `chunks`, each
+/// padded to `shard_size`. A short last stripe is padded with all-zero
+/// shards, which are never stored since every decoder can recreate them.
+pub fn encode_stripe(
+    chunks: &[Vec<u8>],
+    shard_size: usize,
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Vec<Vec<u8>>, reed_solomon_erasure::Error> {
+    let codec = ReedSolomon::new(data_shards, parity_shards)?;
+    if chunks.len() > data_shards {
+        return Err(reed_solomon_erasure::Error::TooManyShards);
+    }
+    if chunks.iter().any(|chunk| chunk.len() > shard_size) {
+        return Err(reed_solomon_erasure::Error::IncorrectShardSize);
+    }
+    let mut shards: Vec<Vec<u8>> = chunks
+        .iter()
+        .map(|chunk| padded(chunk, shard_size))
+        .collect();
+    shards.resize(data_shards + parity_shards, vec![0; shard_size]);
+    codec.encode(&mut shards)?;
+    Ok(shards.split_off(data_shards))
+}
+
+/// Rebuilds the missing chunks of one stripe from its available chunks and
+/// decrypted parity shards. Returns every chunk of the stripe, still padded
+/// to the shard size.
+pub fn reconstruct_stripe(
+    layout: &ErasureLayout,
+    chunks: Vec<Option<Vec<u8>>>,
+    parity: Vec<Option<Vec<u8>>>,
+) -> Result<Vec<Vec<u8>>, reed_solomon_erasure::Error> {
+    let codec = ReedSolomon::new(layout.data_shards, layout.parity_shards)?;
+    let stripe_len = chunks.len();
+    let mut shards: Vec<Option<Vec<u8>>> = chunks
+        .into_iter()
+        .map(|chunk| chunk.map(|chunk| padded(&chunk, layout.shard_size)))
+        .collect();
+    shards.resize(layout.data_shards, Some(vec![0; layout.shard_size]));
+    shards.extend(parity);
+    codec.reconstruct_data(&mut shards)?;
+    Ok(shards
+        .into_iter()
+        .take(stripe_len)
+        .map(|shard| shard.expect("reconstructed data shards are present"))
+        .collect())
+}
+
+fn padded(chunk: &[u8], shard_size: usize) -> Vec<u8> {
+    let mut shard = chunk.to_vec();
+    shard.resize(shard_size, 0);
+    shard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::chunker::hash_chunk;
+
+    const DATA_SHARDS: usize = 3;
+    const PARITY_SHARDS: usize = 2;
+
+    /// Every way of dropping up to `max` of `count` shards.
+    fn drop_patterns(count: usize, max: usize) -> Vec<Vec<bool>> {
+        (0u32..1 << count)
+            .filter(|mask| mask.count_ones() as usize <= max)
+            .map(|mask| (0..count).map(|i| mask & (1 << i) != 0).collect())
+            .collect()
+    }
+
+    fn check_stripe(chunks: &[Vec<u8>], shard_size: usize) {
+        let layout = ErasureLayout {
+            data_shards: DATA_SHARDS,
+            parity_shards: PARITY_SHARDS,
+            shard_size,
+            parity_hashes: Vec::new(),
+        };
+        let parity = encode_stripe(chunks, shard_size, DATA_SHARDS, PARITY_SHARDS).unwrap();
+        assert_eq!(parity.len(), PARITY_SHARDS);
+        assert!(parity.iter().all(|shard| shard.len() == shard_size));
+
+        for dropped in drop_patterns(chunks.len() + PARITY_SHARDS, PARITY_SHARDS) {
+            let (dropped_chunks, dropped_parity) = dropped.split_at(chunks.len());
+            let available = chunks
+                .iter()
+                .zip(dropped_chunks)
+                .map(|(chunk, dropped)| (!dropped).then(|| chunk.clone()))
+                .collect();
+            let available_parity = parity
+                .iter()
+                .zip(dropped_parity)
+                .map(|(shard, dropped)| (!dropped).then(|| shard.clone()))
+                .collect();
+            let rebuilt = reconstruct_stripe(&layout, available, available_parity).unwrap();
+            assert_eq!(rebuilt.len(), chunks.len());
+            for (mut data, chunk) in rebuilt.into_iter().zip(chunks) {
+                assert_eq!(data.len(), shard_size);
+                data.truncate(chunk.len());
+                assert_eq!(hash_chunk(&data), hash_chunk(chunk), "dropped {:?}", dropped);
+            }
+        }
+    }
+
+    fn chunk(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn full_stripes_survive_losing_parity_count_shards() {
+        // Chunks of different lengths are zero-padded to the shard size.
+        let chunks = vec![chunk(100, 1), chunk(37, 2), chunk(100, 3)];
+        check_stripe(&chunks, 100);
+    }
+
+    #[test]
+    fn short_last_stripes_survive_losing_parity_count_shards() {
+        check_stripe(&[chunk(60, 4), chunk(13, 5)], 100);
+        check_stripe(&[chunk(1, 6)], 100);
+    }
+
+    #[test]
+    fn losing_more_than_parity_count_shards_fails() {
+        let chunks = vec![chunk(50, 7), chunk(50, 8), chunk(50, 9)];
+        let parity = encode_stripe(&chunks, 50, DATA_SHARDS, PARITY_SHARDS).unwrap();
+        let layout = ErasureLayout {
+            data_shards: DATA_SHARDS,
+            parity_shards: PARITY_SHARDS,
+            shard_size: 50,
+            parity_hashes: Vec::new(),
+        };
+        let result = reconstruct_stripe(
+            &layout,
+            vec![None, None, Some(chunks[2].clone())],
+            vec![Some(parity[0].clone()), None],
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        assert!(encode_stripe(&[chunk(101, 0)], 100, DATA_SHARDS, PARITY_SHARDS).is_err());
+        let too_many = vec![chunk(10, 0); DATA_SHARDS + 1];
+        assert!(encode_stripe(&too_many, 10, DATA_SHARDS, PARITY_SHARDS).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::node::crypto::CryptoError;
+use crate::node::wire::WireError;
+use libp2p::gossipsub;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a node could not be started or an operation on it failed.
+#[derive(Debug)]
+pub enum NodeError {
+    /// A required builder setting is missing or invalid.
+    Config(String),
+    /// Reading or writing the data directory or chunk store failed.
+    Storage(io::Error),
+    /// A message or record could not be encoded or decoded.
+    Serialization(String),
+    /// Data was not encrypted or signed with the expected keys, or a peer
+    /// failed the password handshake.
+    Authentication(String),
+    /// The swarm could not be set up, or a request could not reach the network.
+    Network(String),
+    /// Nothing matched the request, e.g. no files for an upload pattern.
+    NotFound(String),
+    /// A downloaded file does not match its manifest.
+    Integrity(String),
+    /// A download was cancelled before it finished.
+    Cancelled(String),
+    /// A download gave up before every chunk arrived.
+    DownloadFailed {
+        reason: String,
+        missing_chunks: Vec<usize>,
+    },
+    /// The node is not running.
+    Stopped,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::Config(e) => write!(f, "invalid configuration: {}", e),
+            NodeError::Storage(e) => write!(f, "storage error: {}", e),
+            NodeError::Serialization(e) => write!(f, "serialization error: {}", e),
+            NodeError::Authentication(e) => write!(f, "authentication failed: {}", e),
+            NodeError::Network(e) => write!(f, "network error: {}", e),
+            NodeError::NotFound(e) => write!(f, "not found: {}", e),
+            NodeError::Integrity(e) => write!(f, "integrity check failed: {}", e),
+            NodeError::Cancelled(e) => write!(f, "cancelled: {}", e),
+            NodeError::DownloadFailed {
+                reason,
+                missing_chunks,
+            } => write!(
+                f,
+                "download failed: {} ({} chunk(s) missing)",
+                reason,
+                missing_chunks.len()
+            ),
+            NodeError::Stopped => write!(f, "node is not running"),
+        }
+    }
+}
+
+impl Error for NodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NodeError::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NodeError {
+    fn from(e: io::Error) -> Self {
+        NodeError::Storage(e)
+    }
+}
+
+impl From<serde_json::Error> for NodeError {
+    fn from(e: serde_json::Error) -> Self {
+        NodeError::Serialization(e.to_string())
+    }
+}
+
+impl From<WireError> for NodeError {
+    fn from(e: WireError) -> Self {
+        NodeError::Serialization(e.to_string())
+    }
+}
+
+impl From<CryptoError> for NodeError {
+    fn from(e: CryptoError) -> Self {
+        match e {
+            CryptoError::KeyDerivation(_) => NodeError::Config(e.to_string()),
+            CryptoError::Malformed | CryptoError::Decryption => {
+                NodeError::Authentication(e.to_string())
+            }
+        }
+    }
+}
+
+impl From<libp2p::identity::SigningError> for NodeError {
+    fn from(e: libp2p::identity::SigningError) -> Self {
+        NodeError::Authentication(e.to_string())
+    }
+}
+
+impl From<gossipsub::PublishError> for NodeError {
+    fn from(e: gossipsub::PublishError) -> Self {
+        NodeError::Network(e.to_string())
+    }
+}
+
+impl From<gossipsub::SubscriptionError> for NodeError {
+    fn from(e: gossipsub::SubscriptionError) -> Self {
+        NodeError::Network(e.to_string())
+    }
+}
+
+impl From<libp2p::TransportError<io::Error>> for NodeError {
+    fn from(e: libp2p::TransportError<io::Error>) -> Self {
+        NodeError::Network(e.to_string())
+    }
+}
@@ -0,0 +1,39 @@
+use crate::node::FileTransferLog;
+use libp2p::{Multiaddr, PeerId};
+use std::path::PathBuf;
+
+/// Something that happened on a running node, delivered to every
+/// `NodeHandle::subscribe_events` receiver.
+#[derive(Debug, Clone)]
+pub enum NodeEvent {
+    /// The node started listening on an address.
+    Listening { address: Multiaddr },
+    /// A peer proved it knows the topic secret.
+    PeerAuthenticated { peer_id: PeerId },
+    /// A peer failed authentication and was blacklisted.
+    PeerRejected { peer_id: PeerId, reason: String },
+    /// This node stored a chunk a peer sent it.
+    ChunkStored {
+        peer_id: PeerId,
+        file_name: String,
+        chunk_hash: String,
+    },
+    /// A replica announced that it stored a chunk.
+    TransferLogged(FileTransferLog),
+    /// A chunk of a download arrived.
+    DownloadProgress {
+        file_name: String,
+        received_chunks: usize,
+        total_chunks: usize,
+    },
+    DownloadCompleted { file_name: String, path: PathBuf },
+    DownloadFailed {
+        file_name: String,
+        reason: String,
+        /// Indices of the chunks that never arrived; empty when the download
+        /// failed before its manifest or after reassembly.
+        missing_chunks: Vec<usize>,
+    },
+    /// A chat message published on the topic.
+    Message { peer_id: PeerId, text: String },
+}
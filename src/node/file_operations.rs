@@ -1,8 +1,159 @@
-use rfd::FileDialog;
-use std::path::PathBuf;
-
-/// Opens a file dialog for the user to select a file.
-/// Returns the path to the selected file.
-pub fn select_file() -> Option<PathBuf> {
-    FileDialog::new().pick_file()
-}
\ No newline at end of file
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Resolves an upload argument to the files it names. `spec` may be a file,
+/// a directory (every regular file directly inside it), or a glob pattern.
+/// The result is sorted and never empty.
+pub fn resolve_upload_paths(spec: &str) -> io::Result<Vec<PathBuf>> {
+    let path = Path::new(spec);
+    let mut files = if path.is_file() {
+        vec![path.to_path_buf()]
+    } else if path.is_dir() {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files
+    } else {
+        let entries = glob::glob(spec)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.is_file() {
+                files.push(entry);
+            }
+        }
+        files
+    };
+
+    if files.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no files match '{}'", spec),
+        ));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Drops repeats of the same file from the files of one upload and checks
+/// that the rest have distinct names, as every file is published under its
+/// name alone and a second file of the same name would replace the first.
+pub fn unique_upload_names(files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let mut by_name: HashMap<String, (PathBuf, PathBuf)> = HashMap::new();
+    let mut unique = Vec::new();
+    for file in files {
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' has no UTF-8 file name", file.display()),
+                )
+            })?
+            .to_string();
+        let canonical = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+        match by_name.get(&name) {
+            Some((_, seen)) if *seen == canonical => continue,
+            Some((other, _)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "'{}' and '{}' would both be published as '{}'",
+                        other.display(),
+                        file.display(),
+                        name
+                    ),
+                ))
+            }
+            None => {
+                by_name.insert(name, (file.clone(), canonical));
+                unique.push(file);
+            }
+        }
+    }
+    Ok(unique)
+}
+
+/// Writes a download chunk by chunk to `<path>.part` and moves it to `path`
+/// once every chunk is in, so `path` only ever holds a complete file. The
+/// partial file is removed if the writer is dropped before
+/// [`ReassemblyWriter::commit`].
+pub struct ReassemblyWriter {
+    path: PathBuf,
+    part_path: PathBuf,
+    file: Option<BufWriter<fs::File>>,
+    committed: bool,
+}
+
+impl ReassemblyWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        let file = fs::File::create(&part_path)?;
+        Ok(ReassemblyWriter {
+            path: path.to_path_buf(),
+            part_path,
+            file: Some(BufWriter::new(file)),
+            committed: false,
+        })
+    }
+
+    /// Appends the next chunk of the file.
+    pub fn write_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.write_all(data),
+            None => Err(io::Error::other("reassembly already committed")),
+        }
+    }
+
+    /// Flushes the file to disk and renames it into place.
+    pub fn commit(mut self) -> io::Result<PathBuf> {
+        if let Some(file) = self.file.take() {
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.part_path, &self.path)?;
+        self.committed = true;
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for ReassemblyWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.part_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_reject_files_of_the_same_name() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for sub in ["a", "b"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+            fs::write(dir.path().join(sub).join("data.csv"), sub).unwrap();
+        }
+        let a = dir.path().join("a/data.csv");
+        let b = dir.path().join("b/data.csv");
+        let error = unique_upload_names(vec![a.clone(), b]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("'data.csv'"), "{}", error);
+
+        // The same file named twice is uploaded once.
+        let again = dir.path().join("a/../a/data.csv");
+        assert_eq!(unique_upload_names(vec![a.clone(), again]).unwrap(), vec![a]);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::node::chunk_store::{ChunkStore, FsChunkStore};
+use crate::node::command::{
+    Chunking, DownloadInfo, ErasureCoding, FileEntry, LogQuery, NodeCommand, PeerInfo, PeerScore, PutOptions, PutReport, Redundancy,
+};
+use crate::node::crypto::TopicKeys;
+use crate::node::error::NodeError;
+use crate::node::event::NodeEvent;
+use crate::node::storage_manager::StorageLayout;
+use crate::node::{identity, replication, scheduler, FileTransferLog, NodeConfig};
+use libp2p::{Multiaddr, PeerId};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+const COMMAND_QUEUE: usize = 16;
+const EVENT_QUEUE: usize = 64;
+
+/// A peer of the file sharing network. Created with [`Node::builder`], it does
+/// nothing until [`Node::run`] is awaited; the [`NodeHandle`] returned
+/// alongside it drives the node while it runs.
+pub struct Node {
+    config: NodeConfig,
+    commands: mpsc::Receiver<NodeCommand>,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl Node {
+    pub fn builder() -> NodeBuilder {
+        NodeBuilder::default()
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        self.config.keypair.public().to_peer_id()
+    }
+
+    /// Serves the network and answers the handles until every handle is dropped.
+    pub async fn run(self) -> Result<(), NodeError> {
+        crate::node::run_node(self.config, self.commands, self.events).await
+    }
+}
+
+/// Settings for a [`Node`]. A topic, a secret and a data directory are required.
+#[derive(Default)]
+pub struct NodeBuilder {
+    topic: Option<String>,
+    secret: Option<String>,
+    data_dir: Option<PathBuf>,
+    identity_path: Option<PathBuf>,
+    chunk_store: Option<Arc<dyn ChunkStore>>,
+    listen_addrs: Vec<Multiaddr>,
+    bootstrap_peers: Vec<Multiaddr>,
+    mdns: Option<bool>,
+    relay_server: bool,
+    replication_factor: Option<usize>,
+    chunking: Chunking,
+    download_deadline: Option<Duration>,
+}
+
+impl NodeBuilder {
+    /// Gossipsub topic shared by the peers of one network.
+    pub fn topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Password every peer of the topic knows; chunks and messages are
+    /// encrypted with keys derived from it.
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// Directory holding chunks, manifests, downloads, keys and logs.
+    pub fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// Key file of the node identity; defaults to `<data_dir>/keys/identity.key`.
+    pub fn identity_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.identity_path = Some(path.into());
+        self
+    }
+
+    /// Chunk backend; defaults to a filesystem store under `<data_dir>/chunks`.
+    pub fn chunk_store(mut self, store: Arc<dyn ChunkStore>) -> Self {
+        self.chunk_store = Some(store);
+        self
+    }
+
+    /// Adds an address to listen on. Without any, the node listens on every
+    /// interface over TCP and QUIC with OS-assigned ports.
+    pub fn listen_on(mut self, addr: Multiaddr) -> Self {
+        self.listen_addrs.push(addr);
+        self
+    }
+
+    /// Adds a peer to dial on start and redial whenever it drops, e.g.
+    /// `/ip4/10.0.0.5/tcp/4001/p2p/12D3KooW...`. Peers reached this way also
+    /// seed the DHT.
+    pub fn bootstrap_peer(mut self, addr: Multiaddr) -> Self {
+        self.bootstrap_peers.push(addr);
+        self
+    }
+
+    /// Whether to discover peers on the local network with mDNS; on by default.
+    /// Peers are found through the DHT either way.
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.mdns = Some(enabled);
+        self
+    }
+
+    /// Whether to relay connections for peers behind NAT; off by default.
+    /// Peers behind NAT reserve a slot on relays they dialed.
+    pub fn relay_server(mut self, enabled: bool) -> Self {
+        self.relay_server = enabled;
+        self
+    }
+
+    /// Peers keeping a copy of each uploaded chunk unless an upload asks for
+    /// another count; 3 by default.
+    pub fn replication_factor(mut self, replicas: usize) -> Self {
+        self.replication_factor = Some(replicas);
+        self
+    }
+
+    /// How files uploaded without a chunking scheme of their own are split
+    /// into chunks; fixed-size by default.
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// How long a download may take before it fails; 10 minutes by default.
+    pub fn download_deadline(mut self, deadline: Duration) -> Self {
+        self.download_deadline = Some(deadline);
+        self
+    }
+
+    /// Prepares the data directory, identity and topic keys of the node.
+    pub fn build(self) -> Result<(Node, NodeHandle), NodeError> {
+        let topic = self
+            .topic
+            .ok_or_else(|| NodeError::Config("a topic is required".to_string()))?;
+        let secret = self
+            .secret
+            .ok_or_else(|| NodeError::Config("a secret is required".to_string()))?;
+        let data_dir = self
+            .data_dir
+            .ok_or_else(|| NodeError::Config("a data directory is required".to_string()))?;
+
+        let layout = StorageLayout::new(data_dir);
+        layout.create_dirs()?;
+        let chunk_store = match self.chunk_store {
+            Some(store) => store,
+            None => Arc::new(FsChunkStore::new(layout.chunks_dir())?),
+        };
+        let identity_path = self
+            .identity_path
+            .unwrap_or_else(|| layout.identity_key_path());
+        let keypair = identity::load_or_generate_keypair(&identity_path)?;
+        let keys = TopicKeys::derive(&secret, &topic)?;
+        let replication_factor = self
+            .replication_factor
+            .unwrap_or(replication::DEFAULT_REPLICATION_FACTOR);
+        if replication_factor == 0 {
+            return Err(NodeError::Config(
+                "the replication factor must be at least 1".to_string(),
+            ));
+        }
+        super::check_chunking(self.chunking)?;
+        let listen_addrs = if self.listen_addrs.is_empty() {
+            vec![
+                "/ip4/0.0.0.0/udp/0/quic-v1".parse().expect("valid multiaddr"),
+                "/ip4/0.0.0.0/tcp/0".parse().expect("valid multiaddr"),
+            ]
+        } else {
+            self.listen_addrs
+        };
+
+        let local_peer_id = keypair.public().to_peer_id();
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE);
+        let (event_tx, _) = broadcast::channel(EVENT_QUEUE);
+        let node = Node {
+            config: NodeConfig {
+                topic,
+                keys,
+                keypair,
+                layout,
+                chunk_store,
+                listen_addrs,
+                bootstrap_peers: self.bootstrap_peers,
+                mdns: self.mdns.unwrap_or(true),
+                relay_server: self.relay_server,
+                replication_factor,
+                chunking: self.chunking,
+                download_deadline: self
+                    .download_deadline
+                    .unwrap_or(scheduler::DEFAULT_DOWNLOAD_DEADLINE),
+            },
+            commands: command_rx,
+            events: event_tx.clone(),
+        };
+        let handle = NodeHandle {
+            local_peer_id,
+            commands: command_tx,
+            events: event_tx,
+        };
+        Ok((node, handle))
+    }
+}
+
+/// Drives a running [`Node`]. Cheap to clone; the node stops once every
+/// handle is dropped.
+#[derive(Clone)]
+pub struct NodeHandle {
+    local_peer_id: PeerId,
+    commands: mpsc::Sender<NodeCommand>,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl NodeHandle {
+    pub fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id
+    }
+
+    /// Uploads every file named by `paths` (files, directories or glob
+    /// patterns) and waits until every replica has answered.
+    pub async fn put(&self, paths: Vec<String>) -> Result<Vec<PutReport>, NodeError> {
+        self.put_with(paths, PutOptions::default()).await
+    }
+
+    /// Like [`NodeHandle::put`], with the upload settings of `options`.
+    pub async fn put_with(
+        &self,
+        paths: Vec<String>,
+        options: PutOptions,
+    ) -> Result<Vec<PutReport>, NodeError> {
+        self.call(|reply| NodeCommand::Put {
+            paths,
+            options,
+            reply,
+        })
+        .await?
+    }
+
+    /// Like [`NodeHandle::put`], keeping each chunk on `replication_factor`
+    /// peers instead of the node's default.
+    pub async fn put_replicated(
+        &self,
+        paths: Vec<String>,
+        replication_factor: usize,
+    ) -> Result<Vec<PutReport>, NodeError> {
+        let options = PutOptions {
+            redundancy: Some(Redundancy::Replication(replication_factor)),
+            ..Default::default()
+        };
+        self.put_with(paths, options).await
+    }
+
+    /// Like [`NodeHandle::put`], erasure coding each file instead of
+    /// replicating it: every chunk and parity shard goes to one peer.
+    pub async fn put_erasure_coded(
+        &self,
+        paths: Vec<String>,
+        coding: ErasureCoding,
+    ) -> Result<Vec<PutReport>, NodeError> {
+        let options = PutOptions {
+            redundancy: Some(Redundancy::ErasureCoding(coding)),
+            ..Default::default()
+        };
+        self.put_with(paths, options).await
+    }
+
+    /// Changes how many peers keep each chunk of a file uploaded from this
+    /// node. Missing replicas are copied on the next audit.
+    pub async fn set_replication_factor(
+        &self,
+        file_name: &str,
+        replication_factor: usize,
+    ) -> Result<(), NodeError> {
+        self.call(|reply| NodeCommand::SetReplication {
+            file_name: file_name.to_string(),
+            replication_factor,
+            reply,
+        })
+        .await?
+    }
+
+    /// Uploads a single file and waits until every replica has answered.
+    pub async fn put_file(&self, path: impl AsRef<Path>) -> Result<PutReport, NodeError> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(NodeError::NotFound(format!("no file at '{}'", path.display())));
+        }
+        let mut reports = self.put(vec![path.to_string_lossy().into_owned()]).await?;
+        reports
+            .pop()
+            .ok_or_else(|| NodeError::NotFound(path.display().to_string()))
+    }
+
+    /// Downloads `file_name` and returns where it was written: `output` when
+    /// given, otherwise `<data_dir>/downloads/<file_name>`.
+    pub async fn get_file(
+        &self,
+        file_name: &str,
+        output: Option<PathBuf>,
+    ) -> Result<PathBuf, NodeError> {
+        self.call(|reply| NodeCommand::Get {
+            file_name: file_name.to_string(),
+            output,
+            reply,
+        })
+        .await?
+    }
+
+    /// Downloads in progress, including ones resumed after a restart.
+    pub async fn downloads(&self) -> Result<Vec<DownloadInfo>, NodeError> {
+        self.call(|reply| NodeCommand::Downloads { reply }).await
+    }
+
+    /// Stops downloading `file_name`; waiting [`NodeHandle::get_file`] calls
+    /// fail with [`NodeError::Cancelled`].
+    pub async fn cancel_download(&self, file_name: &str) -> Result<(), NodeError> {
+        self.call(|reply| NodeCommand::CancelDownload {
+            file_name: file_name.to_string(),
+            reply,
+        })
+        .await?
+    }
+
+    /// Files this node holds a manifest for.
+    pub async fn list_files(&self) -> Result<Vec<FileEntry>, NodeError> {
+        self.call(|reply| NodeCommand::List { reply }).await?
+    }
+
+    /// Authenticated peers of this session.
+    pub async fn peers(&self) -> Result<Vec<PeerInfo>, NodeError> {
+        self.call(|reply| NodeCommand::Peers { reply }).await
+    }
+
+    /// The peer score table, best first.
+    pub async fn scores(&self) -> Result<Vec<PeerScore>, NodeError> {
+        self.call(|reply| NodeCommand::Scores { reply }).await
+    }
+
+    pub async fn logs(&self, query: LogQuery) -> Result<Vec<FileTransferLog>, NodeError> {
+        self.call(|reply| NodeCommand::Logs { query, reply }).await
+    }
+
+    /// Publishes a chat message to the topic.
+    pub async fn publish(&self, text: &str) -> Result<(), NodeError> {
+        self.call(|reply| NodeCommand::Publish {
+            text: text.to_string(),
+            reply,
+        })
+        .await?
+    }
+
+    /// Receives the events of the node from now on. A receiver that falls
+    /// too far behind skips the oldest events.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    async fn call<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> NodeCommand,
+    ) -> Result<T, NodeError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| NodeError::Stopped)?;
+        response.await.map_err(|_| NodeError::Stopped)
+    }
+}
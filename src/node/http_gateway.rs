@@ -0,0 +1,150 @@
+use crate::node::command::LogQuery;
+use crate::node::manifest;
+use crate::node::{NodeError, NodeHandle};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+/// How long `GET /files/{name}` waits for the network before giving up.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Serves the HTTP gateway on `addr`, forwarding requests to `node`:
+///
+/// - `PUT /files/{name}` uploads the request body as `name`
+/// - `GET /files/{name}` downloads `name` and streams it back
+/// - `GET /peers` returns the peer score table
+/// - `GET /logs?peer_id=&file_name=&date=YYYY-MM-DD` returns matching transfer logs
+pub async fn serve(addr: SocketAddr, node: NodeHandle) -> io::Result<()> {
+    let app = Router::new()
+        .route("/files/:name", put(put_file).get(get_file))
+        .route("/peers", get(get_peers))
+        .route("/logs", get(get_logs))
+        .with_state(node);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    println!("HTTP gateway listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await
+}
+
+async fn put_file(
+    State(node): State<NodeHandle>,
+    Path(name): Path<String>,
+    body: Body,
+) -> Response {
+    if !manifest::is_plain_file_name(&name) {
+        return (StatusCode::BAD_REQUEST, "invalid file name").into_response();
+    }
+
+    // The upload flow reads from a path named like the file, so the body is
+    // staged in a private directory first.
+    let staging_dir =
+        std::env::temp_dir().join(format!("p2p_sys-upload-{:016x}", rand::random::<u64>()));
+    let staged_file = staging_dir.join(&name);
+    if let Err(e) = stage_body(&staged_file, body).await {
+        let _ = tokio::fs::remove_dir_all(&staging_dir).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    let result = node.put_file(&staged_file).await;
+    let _ = tokio::fs::remove_dir_all(&staging_dir).await;
+
+    match result {
+        Ok(report) => {
+            let status = if report.is_complete() {
+                StatusCode::CREATED
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            (status, Json(report)).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+async fn stage_body(path: &std::path::Path, body: Body) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        // Only this user may read the plaintext while it is staged.
+        tokio::fs::DirBuilder::new().mode(0o700).create(dir).await?;
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut stream = body.into_data_stream().map_err(io::Error::other);
+    while let Some(data) = stream.try_next().await? {
+        file.write_all(&data).await?;
+    }
+    file.flush().await
+}
+
+async fn get_file(
+    State(node): State<NodeHandle>,
+    Path(name): Path<String>,
+) -> Response {
+    if !manifest::is_plain_file_name(&name) {
+        return (StatusCode::BAD_REQUEST, "invalid file name").into_response();
+    }
+
+    let path = match tokio::time::timeout(DOWNLOAD_TIMEOUT, node.get_file(&name, None)).await {
+        Ok(Ok(path)) => path,
+        Ok(Err(e)) => return error_response(e),
+        Err(_) => {
+            return (StatusCode::GATEWAY_TIMEOUT, "download timed out").into_response();
+        }
+    };
+
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let length = file.metadata().await.map(|metadata| metadata.len());
+    let mut response = Body::from_stream(ReaderStream::new(file)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(manifest::mime_hint(&name)),
+    );
+    if let Ok(length) = length {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    response
+}
+
+async fn get_peers(State(node): State<NodeHandle>) -> Response {
+    match node.scores().await {
+        Ok(scores) => Json(scores).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn get_logs(
+    State(node): State<NodeHandle>,
+    Query(query): Query<LogQuery>,
+) -> Response {
+    match node.logs(query).await {
+        Ok(logs) => Json(logs).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+fn error_response(error: NodeError) -> Response {
+    let status = match error {
+        NodeError::NotFound(_) => StatusCode::NOT_FOUND,
+        NodeError::Storage(_) | NodeError::Serialization(_) | NodeError::Config(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        NodeError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+        NodeError::Cancelled(_) => StatusCode::CONFLICT,
+        NodeError::Authentication(_)
+        | NodeError::Network(_)
+        | NodeError::Integrity(_)
+        | NodeError::DownloadFailed { .. } => StatusCode::BAD_GATEWAY,
+    };
+    (status, error.to_string()).into_response()
+}
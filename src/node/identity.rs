@@ -0,0 +1,45 @@
+use libp2p::identity::Keypair;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Loads the node's keypair from `path`, or generates a new Ed25519 keypair
+/// and saves it there if the file does not exist yet. Keeping the keypair
+/// keeps the peer id, and with it the node's storage, stable across restarts.
+pub fn load_or_generate_keypair(path: &Path) -> io::Result<Keypair> {
+    match fs::read(path) {
+        Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            let encoded = keypair
+                .to_protobuf_encoding()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_private(path, &encoded)?;
+            println!("Generated new node identity at {}", path.display());
+            Ok(keypair)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    fs::write(path, data)
+}
@@ -0,0 +1,498 @@
+use crate::node::chunker::{ChunkMetadata, MAX_CHUNK_SIZE};
+use crate::node::{crypto, erasure};
+use libp2p::identity::{Keypair, PublicKey, SigningError};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Describes a file as the ordered list of chunks it was split into.
+/// The manifest is signed by the publishing peer so downloaders can verify
+/// both who published it and that the reassembled bytes match.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileManifest {
+    pub file_name: String,
+    pub total_length: u64,
+    pub mime_hint: String,
+    #[serde(with = "digests")]
+    pub chunk_hashes: Vec<String>,
+    pub chunk_sizes: Vec<usize>,
+    pub merkle_root: String,
+    pub publisher: String,
+    /// Parity shards protecting the chunks; absent for replicated files.
+    #[serde(default)]
+    pub erasure: Option<Box<ErasureLayout>>,
+    /// Protobuf-encoded public key of the publisher.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// How the chunks of an erasure-coded file are grouped into stripes of
+/// `data_shards` chunks, each protected by `parity_shards` parity shards.
+/// Any `data_shards` shards of a stripe rebuild its missing chunks.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ErasureLayout {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    /// Length the encrypted chunks of a stripe are zero-padded to for coding.
+    pub shard_size: usize,
+    /// Digests of the encrypted parity shards, `parity_shards` per stripe.
+    #[serde(with = "digests")]
+    pub parity_hashes: Vec<String>,
+}
+
+/// Which of the chunks and parity shards of a manifest, in
+/// [`FileManifest::shard_hashes`] order, a peer can serve; one bit each
+/// rather than the digests again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ShardBitmap {
+    bits: Vec<u8>,
+}
+
+/// The manifest fields covered by the publisher's signature.
+#[derive(serde::Serialize)]
+struct SignedFields<'a> {
+    file_name: &'a str,
+    total_length: u64,
+    mime_hint: &'a str,
+    chunk_hashes: &'a [String],
+    chunk_sizes: &'a [usize],
+    merkle_root: &'a str,
+    publisher: &'a str,
+    // Left out when absent so manifests of replicated files keep their signature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    erasure: Option<&'a ErasureLayout>,
+}
+
+impl FileManifest {
+    /// Builds and signs a manifest for `file_name` from its chunk metadata.
+    pub fn new(
+        file_name: &str,
+        chunks: &[ChunkMetadata],
+        erasure: Option<ErasureLayout>,
+        keypair: &Keypair,
+    ) -> Result<Self, SigningError> {
+        let chunk_hashes: Vec<String> = chunks.iter().map(|c| c.hash.clone()).collect();
+        let mut manifest = FileManifest {
+            file_name: file_name.to_string(),
+            total_length: chunks.iter().map(|c| c.size as u64).sum(),
+            mime_hint: mime_hint(file_name).to_string(),
+            merkle_root: merkle_root(&chunk_hashes),
+            chunk_hashes,
+            chunk_sizes: chunks.iter().map(|c| c.size).collect(),
+            publisher: keypair.public().to_peer_id().to_string(),
+            erasure: erasure.map(Box::new),
+            public_key: keypair.public().encode_protobuf(),
+            signature: Vec::new(),
+        };
+        manifest.signature = keypair.sign(&manifest.signed_bytes())?;
+        Ok(manifest)
+    }
+
+    pub fn total_chunks(&self) -> usize {
+        self.chunk_hashes.len()
+    }
+
+    /// Digests of everything stored for the file: its chunks, then any
+    /// parity shards.
+    pub fn shard_hashes(&self) -> Vec<String> {
+        let mut hashes = self.chunk_hashes.clone();
+        if let Some(erasure) = &self.erasure {
+            hashes.extend(erasure.parity_hashes.iter().cloned());
+        }
+        hashes
+    }
+
+    /// The chunks and parity shards of each stripe of an erasure-coded
+    /// file; empty for a replicated file.
+    pub fn stripes(&self) -> Vec<Vec<String>> {
+        let Some(erasure) = &self.erasure else {
+            return Vec::new();
+        };
+        let total_chunks = self.total_chunks();
+        (0..erasure.stripe_count(total_chunks))
+            .map(|stripe| {
+                let mut shards = self.chunk_hashes[erasure.stripe_chunks(stripe, total_chunks)].to_vec();
+                shards.extend_from_slice(&erasure.parity_hashes[erasure.stripe_parity(stripe)]);
+                shards
+            })
+            .collect()
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let fields = SignedFields {
+            file_name: &self.file_name,
+            total_length: self.total_length,
+            mime_hint: &self.mime_hint,
+            chunk_hashes: &self.chunk_hashes,
+            chunk_sizes: &self.chunk_sizes,
+            merkle_root: &self.merkle_root,
+            publisher: &self.publisher,
+            erasure: self.erasure.as_deref(),
+        };
+        serde_json::to_vec(&fields).expect("manifest fields always serialize")
+    }
+
+    /// Checks the publisher signature and that the chunk list is consistent
+    /// with the declared length and Merkle root.
+    pub fn verify(&self) -> Result<(), String> {
+        if !is_plain_file_name(&self.file_name) {
+            return Err("file name must not contain a path".to_string());
+        }
+        let public_key = PublicKey::try_decode_protobuf(&self.public_key)
+            .map_err(|e| format!("invalid publisher key: {}", e))?;
+        if public_key.to_peer_id().to_string() != self.publisher {
+            return Err("publisher key does not match publisher peer id".to_string());
+        }
+        if !public_key.verify(&self.signed_bytes(), &self.signature) {
+            return Err("invalid manifest signature".to_string());
+        }
+        if !self.shard_hashes().iter().all(|hash| is_digest(hash)) {
+            return Err("chunk digests must be hex-encoded SHA-256".to_string());
+        }
+        if self.chunk_hashes.len() != self.chunk_sizes.len() {
+            return Err("chunk hash and size lists differ in length".to_string());
+        }
+        if self.chunk_sizes.iter().any(|size| *size > MAX_CHUNK_SIZE) {
+            return Err("a chunk is larger than the chunk size limit".to_string());
+        }
+        if self.chunk_sizes.iter().map(|s| *s as u64).sum::<u64>() != self.total_length {
+            return Err("chunk sizes do not add up to the total length".to_string());
+        }
+        if merkle_root(&self.chunk_hashes) != self.merkle_root {
+            return Err("Merkle root does not match chunk hashes".to_string());
+        }
+        if let Some(erasure) = &self.erasure {
+            erasure.check(&self.chunk_sizes)?;
+        }
+        Ok(())
+    }
+}
+
+impl ShardBitmap {
+    /// Marks the shards of `manifest` for which `held` is true.
+    pub fn new(manifest: &FileManifest, held: impl Fn(&str) -> bool) -> Self {
+        let hashes = manifest.shard_hashes();
+        let mut bits = vec![0u8; hashes.len().div_ceil(8)];
+        for (i, hash) in hashes.iter().enumerate() {
+            if held(hash) {
+                bits[i / 8] |= 1 << (i % 8);
+            }
+        }
+        ShardBitmap { bits }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|byte| *byte == 0)
+    }
+
+    /// The digests of the marked shards of `manifest`.
+    pub fn shard_hashes(&self, manifest: &FileManifest) -> Vec<String> {
+        manifest
+            .shard_hashes()
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.bits.get(i / 8).is_some_and(|byte| byte & (1 << (i % 8)) != 0))
+            .map(|(_, hash)| hash)
+            .collect()
+    }
+}
+
+impl ErasureLayout {
+    pub fn stripe_count(&self, total_chunks: usize) -> usize {
+        total_chunks.div_ceil(self.data_shards)
+    }
+
+    /// Indices of the chunks of `stripe` in the manifest.
+    pub fn stripe_chunks(&self, stripe: usize, total_chunks: usize) -> Range<usize> {
+        let start = stripe * self.data_shards;
+        start..(start + self.data_shards).min(total_chunks)
+    }
+
+    /// Indices of the parity shards of `stripe` in `parity_hashes`.
+    pub fn stripe_parity(&self, stripe: usize) -> Range<usize> {
+        stripe * self.parity_shards..(stripe + 1) * self.parity_shards
+    }
+
+    fn check(&self, chunk_sizes: &[usize]) -> Result<(), String> {
+        if self.data_shards == 0 || self.parity_shards == 0 {
+            return Err("erasure coding needs data and parity shards".to_string());
+        }
+        if self.data_shards + self.parity_shards > erasure::MAX_SHARDS {
+            return Err("too many shards per stripe".to_string());
+        }
+        // Rebuilding a stripe allocates every shard at this size.
+        if self.shard_size > crypto::encrypted_len(MAX_CHUNK_SIZE) {
+            return Err("shard size is larger than the largest chunk".to_string());
+        }
+        if self.parity_hashes.len() != self.stripe_count(chunk_sizes.len()) * self.parity_shards {
+            return Err("parity shard count does not match the chunks".to_string());
+        }
+        if chunk_sizes
+            .iter()
+            .any(|size| crypto::encrypted_len(*size) > self.shard_size)
+        {
+            return Err("a chunk is larger than the shard size".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Computes the Merkle root over hex-encoded chunk digests.
+/// Leaves and inner nodes are hashed with distinct prefixes, and an odd node
+/// at the end of a level is promoted unchanged.
+pub fn merkle_root(chunk_hashes: &[String]) -> String {
+    let mut level: Vec<Vec<u8>> = chunk_hashes
+        .iter()
+        .map(|hash| {
+            let mut hasher = Sha256::new();
+            hasher.update([0u8]);
+            hasher.update(hash.as_bytes());
+            hasher.finalize().to_vec()
+        })
+        .collect();
+
+    if level.is_empty() {
+        return hex::encode(Sha256::digest([]));
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update([1u8]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().to_vec()
+                }
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+
+    hex::encode(&level[0])
+}
+
+/// Whether `hash` is a lowercase hex-encoded SHA-256 digest.
+fn is_digest(hash: &str) -> bool {
+    hash.len() == 2 * digests::DIGEST_LEN
+        && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Serializes digests as hex strings in human-readable formats, so stored
+/// manifests stay readable JSON, and as 32 raw bytes each on the wire.
+mod digests {
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{self, Serialize, Serializer};
+    use std::fmt;
+
+    pub const DIGEST_LEN: usize = 32;
+
+    pub fn serialize<S: Serializer>(hashes: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return hashes.serialize(serializer);
+        }
+        let mut blob = Vec::with_capacity(hashes.len() * DIGEST_LEN);
+        for hash in hashes {
+            match hex::decode(hash) {
+                Ok(bytes) if bytes.len() == DIGEST_LEN => blob.extend(bytes),
+                _ => return Err(ser::Error::custom(format!("invalid digest {:?}", hash))),
+            }
+        }
+        serializer.serialize_bytes(&blob)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+        if deserializer.is_human_readable() {
+            return Vec::<String>::deserialize(deserializer);
+        }
+        let blob = deserializer.deserialize_byte_buf(Blob)?;
+        if blob.len() % DIGEST_LEN != 0 {
+            return Err(de::Error::invalid_length(blob.len(), &"a multiple of 32 bytes"));
+        }
+        Ok(blob.chunks(DIGEST_LEN).map(hex::encode).collect())
+    }
+
+    struct Blob;
+
+    impl<'de> Visitor<'de> for Blob {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("concatenated 32-byte digests")
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// Whether `name` is a single path component, so it is safe to store under.
+pub fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+/// Guesses a MIME type from the file extension.
+pub fn mime_hint(file_name: &str) -> &'static str {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "md" | "log" => "text/plain",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(hash: &str) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update([0u8]);
+        hasher.update(hash.as_bytes());
+        hasher.finalize().to_vec()
+    }
+
+    fn node(left: &[u8], right: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update([1u8]);
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().to_vec()
+    }
+
+    fn chunks(count: usize) -> Vec<ChunkMetadata> {
+        (0..count)
+            .map(|i| ChunkMetadata {
+                size: 100 + i,
+                hash: crate::node::chunker::hash_chunk(&[i as u8]),
+            })
+            .collect()
+    }
+
+    fn signed(erasure: Option<ErasureLayout>) -> FileManifest {
+        FileManifest::new("notes.txt", &chunks(3), erasure, &Keypair::generate_ed25519()).unwrap()
+    }
+
+    fn layout() -> ErasureLayout {
+        ErasureLayout {
+            data_shards: 2,
+            parity_shards: 1,
+            shard_size: crypto::encrypted_len(102),
+            parity_hashes: vec!["a".repeat(64), "b".repeat(64)],
+        }
+    }
+
+    #[test]
+    fn merkle_root_promotes_the_odd_node() {
+        let hashes: Vec<String> = ["a", "b", "c"].iter().map(|h| h.to_string()).collect();
+        assert_eq!(merkle_root(&hashes[..1]), hex::encode(leaf("a")));
+        let expected = node(&node(&leaf("a"), &leaf("b")), &leaf("c"));
+        assert_eq!(merkle_root(&hashes), hex::encode(expected));
+
+        let five: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        let left = node(&node(&leaf("0"), &leaf("1")), &node(&leaf("2"), &leaf("3")));
+        assert_eq!(merkle_root(&five), hex::encode(node(&left, &leaf("4"))));
+    }
+
+    #[test]
+    fn signed_manifests_verify() {
+        signed(None).verify().unwrap();
+        signed(Some(layout())).verify().unwrap();
+    }
+
+    #[test]
+    fn tampered_fields_fail_the_signature() {
+        let mut manifest = signed(None);
+        manifest.chunk_sizes.swap(0, 2);
+        assert_eq!(manifest.verify().unwrap_err(), "invalid manifest signature");
+
+        let mut manifest = signed(Some(layout()));
+        manifest.erasure.as_mut().unwrap().parity_hashes[0] = "c".repeat(64);
+        assert_eq!(manifest.verify().unwrap_err(), "invalid manifest signature");
+
+        let mut manifest = signed(Some(layout()));
+        manifest.erasure = None;
+        assert_eq!(manifest.verify().unwrap_err(), "invalid manifest signature");
+    }
+
+    #[test]
+    fn path_like_file_names_are_rejected() {
+        for name in ["../notes.txt", "dir/notes.txt", "dir\\notes.txt", "..", ""] {
+            let mut manifest = signed(None);
+            manifest.file_name = name.to_string();
+            assert_eq!(
+                manifest.verify().unwrap_err(),
+                "file name must not contain a path",
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_chunks_are_rejected() {
+        let mut chunks = chunks(2);
+        chunks[1].size = MAX_CHUNK_SIZE + 1;
+        let manifest =
+            FileManifest::new("notes.txt", &chunks, None, &Keypair::generate_ed25519()).unwrap();
+        assert_eq!(
+            manifest.verify().unwrap_err(),
+            "a chunk is larger than the chunk size limit"
+        );
+    }
+
+    #[test]
+    fn oversized_shards_are_rejected() {
+        let mut layout = layout();
+        layout.shard_size = usize::MAX / 2;
+        assert_eq!(
+            signed(Some(layout)).verify().unwrap_err(),
+            "shard size is larger than the largest chunk"
+        );
+    }
+
+    #[test]
+    fn manifests_without_erasure_keep_their_signature() {
+        let manifest = signed(None);
+        let signed_json: serde_json::Value =
+            serde_json::from_slice(&manifest.signed_bytes()).unwrap();
+        assert!(signed_json.get("erasure").is_none());
+
+        // A manifest from before erasure coding has no `erasure` field at all.
+        let mut json = serde_json::to_value(&manifest).unwrap();
+        json.as_object_mut().unwrap().remove("erasure");
+        let legacy: FileManifest = serde_json::from_value(json).unwrap();
+        legacy.verify().unwrap();
+        assert_eq!(legacy.signed_bytes(), manifest.signed_bytes());
+    }
+}
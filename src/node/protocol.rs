@@ -1,34 +1,59 @@
-use libp2p::{
-    request_response::{ProtocolName, RequestResponseCodec},
-    bytes::Bytes,
-};
+use crate::node::manifest::{FileManifest, ShardBitmap};
 use async_trait::async_trait;
-use std::{io, str};
+use bincode::Options;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use std::io;
+
+/// Upper bound on a single encoded request or response. A manifest takes
+/// about 35 bytes per chunk, so this fits files of close to a million chunks.
+pub const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct FileTransferProtocol();
 
-impl ProtocolName for FileTransferProtocol {
-    fn protocol_name(&self) -> &[u8] {
-        b"/file-transfer/1.0.0"
+impl AsRef<str> for FileTransferProtocol {
+    fn as_ref(&self) -> &str {
+        "/file-transfer/2.0.0"
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct FileRequest {
-    pub filename: String,
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum FileRequest {
+    /// Ask a replica to store a chunk of `file_name`.
+    StoreChunk {
+        file_name: String,
+        chunk_hash: String,
+        data: Vec<u8>,
+    },
+    /// Ask a holder for the bytes of a chunk.
+    FetchChunk { chunk_hash: String },
+    /// Ask a provider found in the DHT for the manifest of `file_name`.
+    FetchManifest { file_name: String },
+    /// Ask a replica which of `chunk_hashes` it still holds.
+    CheckChunks { chunk_hashes: Vec<String> },
 }
 
-#[derive(Debug, Clone)]
-pub struct FileResponse {
-    pub content: Vec<u8>,
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum FileResponse {
+    Stored,
+    Chunk { chunk_hash: String, data: Vec<u8> },
+    /// A manifest with the chunks of it the responder can serve.
+    Manifest {
+        manifest: FileManifest,
+        available_chunks: ShardBitmap,
+    },
+    /// The chunks of a `CheckChunks` request the responder holds.
+    Holding { chunk_hashes: Vec<String> },
+    NotFound,
+    Rejected { reason: String },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FileTransferCodec;
 
 #[async_trait]
-impl RequestResponseCodec for FileTransferCodec {
+impl request_response::Codec for FileTransferCodec {
     type Protocol = FileTransferProtocol;
     type Request = FileRequest;
     type Response = FileResponse;
@@ -41,11 +66,8 @@ impl RequestResponseCodec for FileTransferCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        let data = read_length_prefixed(io, 1024).await?;
-        let filename = str::from_utf8(&data)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
-            .to_string();
-        Ok(FileRequest { filename })
+        let data = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+        decode(&data)
     }
 
     async fn read_response<T>(
@@ -56,20 +78,20 @@ impl RequestResponseCodec for FileTransferCodec {
     where
         T: AsyncRead + Unpin + Send,
     {
-        let data = read_length_prefixed(io, 16 * 1024).await?;
-        Ok(FileResponse { content: data })
+        let data = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+        decode(&data)
     }
 
     async fn write_request<T>(
         &mut self,
         _: &Self::Protocol,
         io: &mut T,
-        FileRequest { filename }: FileRequest,
+        request: FileRequest,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_length_prefixed(io, filename.as_bytes()).await?;
+        write_length_prefixed(io, &encode(&request)?).await?;
         io.close().await
     }
 
@@ -77,12 +99,133 @@ impl RequestResponseCodec for FileTransferCodec {
         &mut self,
         _: &Self::Protocol,
         io: &mut T,
-        FileResponse { content }: FileResponse,
+        response: FileResponse,
     ) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_length_prefixed(io, &content).await?;
+        write_length_prefixed(io, &encode(&response)?).await?;
         io.close().await
     }
-}
\ No newline at end of file
+}
+
+pub(crate) fn options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(MAX_MESSAGE_SIZE as u64)
+}
+
+/// Encodes a request or response with bincode, which sends chunk bytes as
+/// they are rather than as a JSON array of numbers.
+pub(crate) fn encode<M: serde::Serialize>(message: &M) -> io::Result<Vec<u8>> {
+    options()
+        .serialize(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub(crate) fn decode<M: serde::de::DeserializeOwned>(data: &[u8]) -> io::Result<M> {
+    options()
+        .deserialize(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads a big-endian `u32` length followed by that many bytes.
+pub async fn read_length_prefixed<T>(io: &mut T, max_size: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut len_bytes = [0u8; 4];
+    io.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds limit of {} bytes", len, max_size),
+        ));
+    }
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// Writes `data` prefixed with its length as a big-endian `u32`.
+pub async fn write_length_prefixed<T>(io: &mut T, data: &[u8]) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    io.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    io.write_all(data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::chunker::{hash_chunk, ChunkMetadata, MAX_CHUNK_SIZE};
+    use crate::node::crypto::encrypted_len;
+    use crate::node::replication::AUDIT_BATCH;
+
+    #[test]
+    fn chunks_are_sent_without_inflation() {
+        let data = vec![0xab; 1024 * 1024];
+        let request = FileRequest::StoreChunk {
+            file_name: "data.bin".to_string(),
+            chunk_hash: "0".repeat(64),
+            data: data.clone(),
+        };
+        let encoded = encode(&request).unwrap();
+        assert!(encoded.len() < data.len() + 128);
+        match decode(&encoded).unwrap() {
+            FileRequest::StoreChunk { data: decoded, .. } => assert_eq!(decoded, data),
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_largest_chunks_fit_in_a_store_request() {
+        let request = FileRequest::StoreChunk {
+            file_name: "x".repeat(255),
+            chunk_hash: "0".repeat(64),
+            data: vec![0; encrypted_len(MAX_CHUNK_SIZE)],
+        };
+        let encoded = encode(&request).unwrap();
+        assert!(decode::<FileRequest>(&encoded).is_ok());
+    }
+
+    #[test]
+    fn audit_batches_fit_in_a_request() {
+        let chunk_hashes = vec![hash_chunk(b"chunk"); AUDIT_BATCH];
+        let request = FileRequest::CheckChunks { chunk_hashes };
+        assert!(encode(&request).unwrap().len() < MAX_MESSAGE_SIZE / 16);
+    }
+
+    #[test]
+    fn manifests_of_100k_chunks_fit_in_a_response() {
+        let chunks: Vec<ChunkMetadata> = (0..100_000u32)
+            .map(|i| ChunkMetadata {
+                size: 15 * 1024,
+                hash: hash_chunk(&i.to_be_bytes()),
+            })
+            .collect();
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let manifest = FileManifest::new("large.bin", &chunks, None, &keypair).unwrap();
+        let available = ShardBitmap::new(&manifest, |hash| hash != chunks[7].hash);
+        let encoded = encode(&FileResponse::Manifest {
+            manifest: manifest.clone(),
+            available_chunks: available,
+        })
+        .unwrap();
+        assert!(encoded.len() < 40 * chunks.len(), "{} bytes", encoded.len());
+        assert!(encoded.len() < MAX_MESSAGE_SIZE);
+
+        let FileResponse::Manifest {
+            manifest: decoded,
+            available_chunks,
+        } = decode(&encoded).unwrap()
+        else {
+            panic!("not a manifest");
+        };
+        decoded.verify().unwrap();
+        assert_eq!(decoded.chunk_hashes, manifest.chunk_hashes);
+        let served = available_chunks.shard_hashes(&decoded);
+        assert_eq!(served.len(), chunks.len() - 1);
+        assert!(!served.contains(&chunks[7].hash));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::node::MyBehaviour;
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::{autonat, relay, Multiaddr, PeerId, Swarm};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Relays a node behind NAT keeps a reservation on at once.
+const MAX_RESERVATIONS: usize = 2;
+
+pub fn new_autonat(local_peer_id: PeerId) -> autonat::Behaviour {
+    autonat::Behaviour::new(
+        local_peer_id,
+        autonat::Config {
+            // Teams share files on private networks too, so probe those addresses.
+            only_global_ips: false,
+            ..Default::default()
+        },
+    )
+}
+
+/// Relay service for `--relay-server`. The default limits stop a circuit
+/// after 128 KiB, far less than a file, so relayed transfers get more room.
+pub fn new_relay_server(local_peer_id: PeerId) -> relay::Behaviour {
+    relay::Behaviour::new(
+        local_peer_id,
+        relay::Config {
+            max_circuit_duration: Duration::from_secs(30 * 60),
+            max_circuit_bytes: 1 << 30,
+            ..Default::default()
+        },
+    )
+}
+
+/// Tracks how each peer is connected and which relays this node can be
+/// reached through.
+#[derive(Default)]
+pub struct Reachability {
+    direct_connections: HashMap<PeerId, usize>,
+    /// Address each peer was last dialed at directly.
+    dialed: HashMap<PeerId, Multiaddr>,
+    /// Relay servers seen through identify, with the address to reach them at.
+    relays: HashMap<PeerId, Multiaddr>,
+    reservations: HashSet<PeerId>,
+}
+
+impl Reachability {
+    pub fn on_connection_established(&mut self, peer_id: PeerId, endpoint: &ConnectedPoint) {
+        if endpoint.is_relayed() {
+            return;
+        }
+        *self.direct_connections.entry(peer_id).or_insert(0) += 1;
+        if let ConnectedPoint::Dialer { address, .. } = endpoint {
+            let mut address = address.clone();
+            if let Some(Protocol::P2p(_)) = address.iter().last() {
+                address.pop();
+            }
+            self.dialed.insert(peer_id, address);
+        }
+    }
+
+    pub fn on_connection_closed(
+        &mut self,
+        peer_id: PeerId,
+        endpoint: &ConnectedPoint,
+        remaining: u32,
+    ) {
+        if !endpoint.is_relayed() {
+            if let Some(count) = self.direct_connections.get_mut(&peer_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.direct_connections.remove(&peer_id);
+                }
+            }
+        }
+        if remaining == 0 {
+            // The reservation ends with the last connection to the relay.
+            self.reservations.remove(&peer_id);
+        }
+    }
+
+    /// Whether this node has a connection to `peer_id` that does not go
+    /// through a relay.
+    pub fn is_direct(&self, peer_id: &PeerId) -> bool {
+        self.direct_connections.contains_key(peer_id)
+    }
+
+    /// Remembers a peer offering the relay service. Only relays this node
+    /// dialed are kept, since a relay that dialed in left no usable address.
+    pub fn add_relay(&mut self, peer_id: PeerId) {
+        if let Some(address) = self.dialed.get(&peer_id) {
+            if self.relays.insert(peer_id, address.clone()).is_none() {
+                println!("Found relay {} at {}", peer_id, address);
+            }
+        }
+    }
+
+    /// Listens through known relays while AutoNAT reports this node as
+    /// unreachable, so peers can connect back and store chunks here.
+    pub fn reserve_if_private(&mut self, swarm: &mut Swarm<MyBehaviour>) {
+        if swarm.behaviour().autonat.nat_status() != autonat::NatStatus::Private {
+            return;
+        }
+        for (relay_peer_id, address) in &self.relays {
+            if self.reservations.len() >= MAX_RESERVATIONS {
+                break;
+            }
+            if self.reservations.contains(relay_peer_id) {
+                continue;
+            }
+            let circuit = address
+                .clone()
+                .with(Protocol::P2p(*relay_peer_id))
+                .with(Protocol::P2pCircuit);
+            match swarm.listen_on(circuit.clone()) {
+                Ok(_) => {
+                    println!("Reserving a slot on relay {}", circuit);
+                    self.reservations.insert(*relay_peer_id);
+                }
+                Err(e) => println!("Failed to listen through relay {}: {}", circuit, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::node::storage_manager::{self, StorageLayout};
+use libp2p::PeerId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+/// Replicas kept of each chunk unless a file asks for another count.
+pub const DEFAULT_REPLICATION_FACTOR: usize = 3;
+/// How often held chunks are checked and under-replicated ones copied.
+pub const AUDIT_INTERVAL: Duration = Duration::from_secs(30);
+/// Chunks one audit asks a holder about. A holder of more is asked about the
+/// next batch on every audit.
+pub const AUDIT_BATCH: usize = 4096;
+
+/// Where the chunks of one uploaded file are kept.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct TrackedFile {
+    replication_factor: usize,
+    holders: BTreeMap<String, BTreeSet<PeerId>>, // chunk hash -> peers that confirmed storing it
+    /// Shards of each erasure-coded stripe, which must sit on distinct peers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stripes: Vec<Vec<String>>,
+}
+
+/// A chunk with fewer live replicas than its file asks for.
+pub struct UnderReplicated {
+    pub file_name: String,
+    pub chunk_hash: String,
+    /// Peers that must not get a new replica: the chunk's holders and, for
+    /// an erasure-coded file, the holders of the rest of its stripe.
+    pub excluded: BTreeSet<PeerId>,
+    pub missing: usize,
+}
+
+/// Tracks which peers hold the chunks of the files uploaded from this node,
+/// persisted in `<data-dir>/replication.json` so it survives a restart.
+/// Holder changes are saved by [`ReplicationManager::flush`] rather than as
+/// they happen, since the file lists every chunk of every tracked file.
+pub struct ReplicationManager {
+    layout: StorageLayout,
+    files: BTreeMap<String, TrackedFile>,
+    dirty: bool, // changes not saved yet
+}
+
+impl ReplicationManager {
+    /// Loads the saved placement. A file that cannot be parsed is reported
+    /// and replaced; the audit finds the replicas again as peers answer.
+    pub fn load(layout: &StorageLayout) -> io::Result<Self> {
+        let files = match fs::read(layout.replication_path()) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                println!("Ignoring unreadable replica placement: {}", e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ReplicationManager {
+            layout: layout.clone(),
+            files,
+            dirty: false,
+        })
+    }
+
+    fn save(&mut self) {
+        let result = serde_json::to_vec(&self.files)
+            .map_err(io::Error::from)
+            .and_then(|bytes| storage_manager::write_atomic(&self.layout.replication_path(), &bytes));
+        match result {
+            Ok(()) => self.dirty = false,
+            Err(e) => println!("Failed to save replica placement: {}", e),
+        }
+    }
+
+    /// Saves the changes made since the last save, if any.
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    /// Starts tracking an uploaded file, keeping what is known about chunks
+    /// it shares with an earlier upload of the same name. `stripes` groups
+    /// the shards of an erasure-coded file by stripe.
+    pub fn track(
+        &mut self,
+        file_name: &str,
+        chunk_hashes: &[String],
+        stripes: Vec<Vec<String>>,
+        replication_factor: usize,
+    ) {
+        let previous = self.files.remove(file_name);
+        let holders = chunk_hashes
+            .iter()
+            .map(|hash| {
+                let known = previous
+                    .as_ref()
+                    .and_then(|file| file.holders.get(hash).cloned())
+                    .unwrap_or_default();
+                (hash.clone(), known)
+            })
+            .collect();
+        self.files.insert(
+            file_name.to_string(),
+            TrackedFile {
+                replication_factor,
+                holders,
+                stripes,
+            },
+        );
+        self.save();
+    }
+
+    /// Changes the replica count of a tracked file. Returns false if the
+    /// file was not uploaded from this node.
+    pub fn set_replication_factor(&mut self, file_name: &str, replication_factor: usize) -> bool {
+        match self.files.get_mut(file_name) {
+            Some(file) => {
+                file.replication_factor = replication_factor;
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn replication_factor(&self, file_name: &str) -> Option<usize> {
+        self.files.get(file_name).map(|file| file.replication_factor)
+    }
+
+    /// The fewest live replicas held of any chunk of `file_name`.
+    pub fn live_replicas(&self, file_name: &str, is_live: impl Fn(&PeerId) -> bool) -> Option<usize> {
+        let file = self.files.get(file_name)?;
+        file.holders
+            .values()
+            .map(|holders| holders.iter().filter(|peer| is_live(peer)).count())
+            .min()
+    }
+
+    /// The peers known to store `chunk_hash`, whichever files contain it.
+    pub fn holders(&self, chunk_hash: &str) -> BTreeSet<PeerId> {
+        self.files
+            .values()
+            .filter_map(|file| file.holders.get(chunk_hash))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Records that `peer` stores `chunk_hash`, in every file containing it.
+    pub fn add_holder(&mut self, chunk_hash: &str, peer: PeerId) {
+        self.add_holders(&[(chunk_hash.to_string(), peer)]);
+    }
+
+    /// Records every `(chunk hash, peer)` pair like
+    /// [`ReplicationManager::add_holder`].
+    pub fn add_holders(&mut self, confirmed: &[(String, PeerId)]) {
+        let mut changed = false;
+        for (chunk_hash, peer) in confirmed {
+            for file in self.files.values_mut() {
+                if let Some(holders) = file.holders.get_mut(chunk_hash) {
+                    changed |= holders.insert(*peer);
+                }
+            }
+        }
+        self.dirty |= changed;
+    }
+
+    /// Forgets `peer` as a holder of every chunk in `chunk_hashes`.
+    pub fn remove_holder(&mut self, chunk_hashes: &[String], peer: &PeerId) {
+        let mut changed = false;
+        for file in self.files.values_mut() {
+            for hash in chunk_hashes {
+                if let Some(holders) = file.holders.get_mut(hash) {
+                    changed |= holders.remove(peer);
+                }
+            }
+        }
+        self.dirty |= changed;
+    }
+
+    /// Every peer recorded as a holder, with the chunks it should hold.
+    pub fn chunks_by_holder(&self) -> HashMap<PeerId, Vec<String>> {
+        let mut by_holder: HashMap<PeerId, BTreeSet<String>> = HashMap::new();
+        for file in self.files.values() {
+            for (hash, holders) in &file.holders {
+                for peer in holders {
+                    by_holder.entry(*peer).or_default().insert(hash.clone());
+                }
+            }
+        }
+        by_holder
+            .into_iter()
+            .map(|(peer, hashes)| (peer, hashes.into_iter().collect()))
+            .collect()
+    }
+
+    /// Chunks held by fewer live peers than their file's replication factor.
+    pub fn under_replicated(&self, is_live: impl Fn(&PeerId) -> bool) -> Vec<UnderReplicated> {
+        let mut chunks = Vec::new();
+        for (file_name, file) in &self.files {
+            let stripe_of: HashMap<&str, &[String]> = file
+                .stripes
+                .iter()
+                .flat_map(|stripe| stripe.iter().map(move |hash| (hash.as_str(), stripe.as_slice())))
+                .collect();
+            for (hash, holders) in &file.holders {
+                let live = holders.iter().filter(|peer| is_live(peer)).count();
+                if live >= file.replication_factor {
+                    continue;
+                }
+                let mut excluded = holders.clone();
+                for sibling in stripe_of.get(hash.as_str()).copied().unwrap_or_default() {
+                    excluded.extend(file.holders.get(sibling).into_iter().flatten());
+                }
+                chunks.push(UnderReplicated {
+                    file_name: file_name.clone(),
+                    chunk_hash: hash.clone(),
+                    excluded,
+                    missing: file.replication_factor - live,
+                });
+            }
+        }
+        chunks
+    }
+}
+
+impl Drop for ReplicationManager {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.repeat(64)).collect()
+    }
+
+    fn manager() -> (tempfile::TempDir, ReplicationManager) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manager = ReplicationManager::load(&StorageLayout::new(dir.path())).unwrap();
+        (dir, manager)
+    }
+
+    #[test]
+    fn chunks_short_of_live_holders_are_under_replicated() {
+        let (_dir, mut manager) = manager();
+        let [a, b] = [PeerId::random(), PeerId::random()];
+        let chunks = hashes(&["1", "2"]);
+        manager.track("notes.txt", &chunks, Vec::new(), 2);
+        manager.add_holders(&[(chunks[0].clone(), a), (chunks[0].clone(), b), (chunks[1].clone(), a)]);
+
+        let under = manager.under_replicated(|_| true);
+        assert_eq!(under.len(), 1);
+        assert_eq!(under[0].chunk_hash, chunks[1]);
+        assert_eq!(under[0].missing, 1);
+        assert_eq!(under[0].excluded, BTreeSet::from([a]));
+
+        // A holder that is not live does not count, but still gets no copy.
+        let under = manager.under_replicated(|peer| *peer != b);
+        assert_eq!(under.len(), 2);
+        assert_eq!(under[0].chunk_hash, chunks[0]);
+        assert_eq!(under[0].excluded, BTreeSet::from([a, b]));
+    }
+
+    #[test]
+    fn removed_holders_leave_chunks_under_replicated() {
+        let (_dir, mut manager) = manager();
+        let peer = PeerId::random();
+        let chunks = hashes(&["1", "2"]);
+        manager.track("notes.txt", &chunks, Vec::new(), 1);
+        manager.add_holder(&chunks[0], peer);
+        manager.add_holder(&chunks[1], peer);
+        assert!(manager.under_replicated(|_| true).is_empty());
+        assert_eq!(manager.chunks_by_holder()[&peer], chunks);
+
+        manager.remove_holder(&chunks[..1], &peer);
+        let under = manager.under_replicated(|_| true);
+        assert_eq!(under.len(), 1);
+        assert_eq!(under[0].chunk_hash, chunks[0]);
+        assert!(under[0].excluded.is_empty());
+        assert_eq!(manager.holders(&chunks[1]), BTreeSet::from([peer]));
+        assert_eq!(manager.live_replicas("notes.txt", |_| true), Some(0));
+    }
+
+    #[test]
+    fn stripe_siblings_holders_are_excluded() {
+        let (_dir, mut manager) = manager();
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+        // Two stripes of two chunks and one parity shard.
+        let shards = hashes(&["1", "2", "3", "4", "5", "6"]);
+        let stripes = vec![shards[..3].to_vec(), shards[3..].to_vec()];
+        manager.track("coded.bin", &shards, stripes, 1);
+        manager.add_holders(&[
+            (shards[1].clone(), peers[1]),
+            (shards[2].clone(), peers[2]),
+            (shards[3].clone(), peers[3]),
+            (shards[4].clone(), peers[0]),
+            (shards[5].clone(), peers[1]),
+        ]);
+
+        let under = manager.under_replicated(|_| true);
+        assert_eq!(under.len(), 1);
+        assert_eq!(under[0].chunk_hash, shards[0]);
+        assert_eq!(under[0].excluded, BTreeSet::from([peers[1], peers[2]]));
+    }
+
+    #[test]
+    fn placement_survives_a_reload() {
+        let (dir, mut manager) = manager();
+        let peer = PeerId::random();
+        let chunks = hashes(&["1"]);
+        manager.track("notes.txt", &chunks, vec![chunks.clone()], 2);
+        manager.add_holder(&chunks[0], peer);
+        manager.flush();
+
+        let reloaded = ReplicationManager::load(&StorageLayout::new(dir.path())).unwrap();
+        assert_eq!(reloaded.replication_factor("notes.txt"), Some(2));
+        assert_eq!(reloaded.holders(&chunks[0]), BTreeSet::from([peer]));
+    }
+}
@@ -0,0 +1,392 @@
+use libp2p::PeerId;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How long a download may take before it fails, unless the node is built
+/// with another deadline.
+pub const DEFAULT_DOWNLOAD_DEADLINE: Duration = Duration::from_secs(10 * 60);
+/// Fetches one download keeps in flight at once.
+pub const MAX_IN_FLIGHT: usize = 16;
+/// Fetches one download keeps in flight to a single holder, so a file held
+/// by several peers is fetched from all of them.
+pub const MAX_IN_FLIGHT_PER_PEER: usize = 4;
+/// How long a fetch may take before its chunk is handed to another holder.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+/// Failed fetches or holder lookups after which a chunk nobody holds is lost.
+pub const MAX_FETCH_ATTEMPTS: u32 = 6;
+/// Wait before the first retry of a chunk; doubled after every failure.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+struct Assignment {
+    peer: PeerId,
+    sent: Instant,
+}
+
+struct Failures {
+    attempts: u32,
+    retry_at: Instant,
+}
+
+/// Decides which holder each chunk of one download is fetched from. A chunk
+/// is assigned to one holder at a time, preferring high-score peers, and
+/// handed to another holder when its fetch fails or times out.
+#[derive(Default)]
+pub struct DownloadScheduler {
+    holders: HashMap<String, Vec<PeerId>>, // chunk hash -> peers announcing it
+    in_flight: HashMap<String, Assignment>,
+    /// Holders a fetch of the chunk timed out from, tried again only when
+    /// no other holder is free.
+    slow: HashMap<String, HashSet<PeerId>>,
+    failures: HashMap<String, Failures>,
+    received: HashSet<String>, // chunk hashes already in the chunk store
+}
+
+/// How far a download got, as reported after every fetched chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub received: usize,
+    pub total: usize,
+    pub in_flight: usize,
+    /// Distinct holders the in-flight fetches were sent to.
+    pub sources: usize,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} chunks, {} in flight from {} peer(s)",
+            self.received, self.total, self.in_flight, self.sources
+        )
+    }
+}
+
+impl DownloadScheduler {
+    pub fn add_holder(&mut self, chunk_hash: &str, peer: PeerId) {
+        let holders = self.holders.entry(chunk_hash.to_string()).or_default();
+        if !holders.contains(&peer) {
+            holders.push(peer);
+        }
+    }
+
+    pub fn has_holders(&self, chunk_hash: &str) -> bool {
+        self.holders
+            .get(chunk_hash)
+            .is_some_and(|holders| !holders.is_empty())
+    }
+
+    /// Drops `peer` as a holder of `chunk_hash` after a failed or invalid
+    /// fetch, freeing the chunk for another holder. Returns whether any
+    /// holder of the chunk is left.
+    pub fn forget_holder(&mut self, chunk_hash: &str, peer: &PeerId) -> bool {
+        if self
+            .in_flight
+            .get(chunk_hash)
+            .is_some_and(|assignment| assignment.peer == *peer)
+        {
+            self.in_flight.remove(chunk_hash);
+        }
+        if let Some(slow) = self.slow.get_mut(chunk_hash) {
+            slow.remove(peer);
+        }
+        match self.holders.get_mut(chunk_hash) {
+            Some(holders) => {
+                holders.retain(|holder| holder != peer);
+                !holders.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_received(&self, chunk_hash: &str) -> bool {
+        self.received.contains(chunk_hash)
+    }
+
+    /// Records a chunk as stored. Returns false if it already was.
+    pub fn on_received(&mut self, chunk_hash: &str) -> bool {
+        self.in_flight.remove(chunk_hash);
+        self.slow.remove(chunk_hash);
+        self.failures.remove(chunk_hash);
+        self.received.insert(chunk_hash.to_string())
+    }
+
+    /// Forgets that `chunk_hash` was received, so it is fetched again after
+    /// the stored copy turned out to be corrupt.
+    pub fn requeue(&mut self, chunk_hash: &str) {
+        self.received.remove(chunk_hash);
+    }
+
+    /// Counts a failed fetch or holder lookup of `chunk_hash` and holds off
+    /// its next attempt, twice as long after every failure.
+    pub fn record_failure(&mut self, chunk_hash: &str) {
+        self.record_failure_at(chunk_hash, Instant::now());
+    }
+
+    fn record_failure_at(&mut self, chunk_hash: &str, now: Instant) {
+        let failures = self
+            .failures
+            .entry(chunk_hash.to_string())
+            .or_insert(Failures {
+                attempts: 0,
+                retry_at: now,
+            });
+        failures.attempts += 1;
+        let backoff = RETRY_BACKOFF
+            .saturating_mul(1 << (failures.attempts - 1).min(16))
+            .min(MAX_RETRY_BACKOFF);
+        failures.retry_at = now + backoff;
+    }
+
+    /// Whether the backoff after the last failure of `chunk_hash` is over.
+    pub fn retry_due(&self, chunk_hash: &str) -> bool {
+        self.retry_due_at(chunk_hash, Instant::now())
+    }
+
+    fn retry_due_at(&self, chunk_hash: &str, now: Instant) -> bool {
+        self.failures
+            .get(chunk_hash)
+            .is_none_or(|failures| failures.retry_at <= now)
+    }
+
+    /// Whether `chunk_hash` failed too often to try again and nobody is
+    /// left holding it.
+    pub fn is_lost(&self, chunk_hash: &str) -> bool {
+        !self.has_holders(chunk_hash)
+            && self
+                .failures
+                .get(chunk_hash)
+                .is_some_and(|failures| failures.attempts >= MAX_FETCH_ATTEMPTS)
+    }
+
+    /// Assigns chunks of `wanted`, in order, to their best free holder until
+    /// the in-flight limits are reached. Returns the fetches to send.
+    pub fn assign(
+        &mut self,
+        wanted: &[String],
+        peer_scores: &HashMap<PeerId, f64>,
+    ) -> Vec<(String, PeerId)> {
+        self.assign_at(wanted, peer_scores, Instant::now())
+    }
+
+    fn assign_at(
+        &mut self,
+        wanted: &[String],
+        peer_scores: &HashMap<PeerId, f64>,
+        now: Instant,
+    ) -> Vec<(String, PeerId)> {
+        let mut load: HashMap<PeerId, usize> = HashMap::new();
+        for assignment in self.in_flight.values() {
+            *load.entry(assignment.peer).or_insert(0) += 1;
+        }
+
+        let mut assigned = Vec::new();
+        for chunk_hash in wanted {
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                break;
+            }
+            if self.received.contains(chunk_hash)
+                || self.in_flight.contains_key(chunk_hash)
+                || !self.retry_due_at(chunk_hash, now)
+            {
+                continue;
+            }
+            let slow = self.slow.get(chunk_hash);
+            // Responsive peers first, then by score.
+            let rank = |peer: &PeerId| {
+                (
+                    !slow.is_some_and(|slow| slow.contains(peer)),
+                    peer_scores.get(peer).copied().unwrap_or(0.0),
+                )
+            };
+            let Some(peer) = self
+                .holders
+                .get(chunk_hash)
+                .into_iter()
+                .flatten()
+                .filter(|peer| load.get(*peer).copied().unwrap_or(0) < MAX_IN_FLIGHT_PER_PEER)
+                .max_by(|a, b| rank(a).partial_cmp(&rank(b)).unwrap_or(Ordering::Equal))
+                .copied()
+            else {
+                continue;
+            };
+            *load.entry(peer).or_insert(0) += 1;
+            self.in_flight.insert(
+                chunk_hash.clone(),
+                Assignment { peer, sent: now },
+            );
+            assigned.push((chunk_hash.clone(), peer));
+        }
+        assigned
+    }
+
+    /// Frees every chunk whose fetch has run longer than [`FETCH_TIMEOUT`]
+    /// so the next [`DownloadScheduler::assign`] after the backoff picks
+    /// another holder.
+    /// Returns the chunks and the holders that did not answer.
+    pub fn take_timed_out(&mut self) -> Vec<(String, PeerId)> {
+        self.take_timed_out_at(Instant::now())
+    }
+
+    fn take_timed_out_at(&mut self, now: Instant) -> Vec<(String, PeerId)> {
+        let timed_out: Vec<(String, PeerId)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, assignment)| now.saturating_duration_since(assignment.sent) >= FETCH_TIMEOUT)
+            .map(|(chunk_hash, assignment)| (chunk_hash.clone(), assignment.peer))
+            .collect();
+        for (chunk_hash, peer) in &timed_out {
+            self.in_flight.remove(chunk_hash);
+            self.slow.entry(chunk_hash.clone()).or_default().insert(*peer);
+            self.record_failure_at(chunk_hash, now);
+        }
+        timed_out
+    }
+
+    pub fn progress(&self, chunk_hashes: &[String]) -> Progress {
+        let sources: HashSet<PeerId> = self
+            .in_flight
+            .values()
+            .map(|assignment| assignment.peer)
+            .collect();
+        Progress {
+            received: chunk_hashes
+                .iter()
+                .filter(|hash| self.received.contains(*hash))
+                .count(),
+            total: chunk_hashes.len(),
+            in_flight: self.in_flight.len(),
+            sources: sources.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("{:064x}", i)).collect()
+    }
+
+    /// A scheduler for `chunks`, each held by every peer in `holders`.
+    fn scheduler(chunks: &[String], holders: &[PeerId]) -> DownloadScheduler {
+        let mut scheduler = DownloadScheduler::default();
+        for chunk_hash in chunks {
+            for peer in holders {
+                scheduler.add_holder(chunk_hash, *peer);
+            }
+        }
+        scheduler
+    }
+
+    #[test]
+    fn assign_stays_within_the_in_flight_limits() {
+        let chunks = hashes(40);
+        let two: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        let mut scheduler = scheduler(&chunks, &two);
+        let assigned = scheduler.assign(&chunks, &HashMap::new());
+        assert_eq!(assigned.len(), 2 * MAX_IN_FLIGHT_PER_PEER);
+        for peer in &two {
+            let count = assigned.iter().filter(|(_, p)| p == peer).count();
+            assert_eq!(count, MAX_IN_FLIGHT_PER_PEER);
+        }
+        // Nothing more until a fetch finishes.
+        assert!(scheduler.assign(&chunks, &HashMap::new()).is_empty());
+        assert!(scheduler.on_received(&assigned[0].0));
+        assert_eq!(scheduler.assign(&chunks, &HashMap::new()).len(), 1);
+
+        let many: Vec<PeerId> = (0..8).map(|_| PeerId::random()).collect();
+        let mut scheduler = self::scheduler(&chunks, &many);
+        assert_eq!(scheduler.assign(&chunks, &HashMap::new()).len(), MAX_IN_FLIGHT);
+    }
+
+    #[test]
+    fn assign_prefers_high_scores_and_skips_received_chunks() {
+        let chunks = hashes(2);
+        let [low, high] = [PeerId::random(), PeerId::random()];
+        let mut scheduler = scheduler(&chunks, &[low, high]);
+        scheduler.on_received(&chunks[0]);
+        let scores = HashMap::from([(low, 1.0), (high, 5.0)]);
+        assert_eq!(scheduler.assign(&chunks, &scores), vec![(chunks[1].clone(), high)]);
+        assert_eq!(scheduler.progress(&chunks).received, 1);
+
+        scheduler.requeue(&chunks[0]);
+        assert!(!scheduler.is_received(&chunks[0]));
+        assert_eq!(scheduler.assign(&chunks, &scores), vec![(chunks[0].clone(), high)]);
+    }
+
+    #[test]
+    fn timed_out_fetches_go_to_another_holder() {
+        let chunks = hashes(1);
+        let [slow, other] = [PeerId::random(), PeerId::random()];
+        let mut scheduler = scheduler(&chunks, &[slow, other]);
+        let scores = HashMap::from([(slow, 5.0), (other, 1.0)]);
+        let start = Instant::now();
+        assert_eq!(scheduler.assign_at(&chunks, &scores, start)[0].1, slow);
+
+        assert!(scheduler
+            .take_timed_out_at(start + FETCH_TIMEOUT - Duration::from_millis(1))
+            .is_empty());
+        let timed_out = start + FETCH_TIMEOUT;
+        assert_eq!(scheduler.take_timed_out_at(timed_out), vec![(chunks[0].clone(), slow)]);
+
+        // The first retry waits out the backoff, then passes over the slow
+        // holder despite its score.
+        assert!(scheduler.assign_at(&chunks, &scores, timed_out).is_empty());
+        let retry = timed_out + RETRY_BACKOFF;
+        assert_eq!(scheduler.assign_at(&chunks, &scores, retry), vec![(chunks[0].clone(), other)]);
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_a_cap() {
+        let chunk = &hashes(1)[0];
+        let mut scheduler = DownloadScheduler::default();
+        let start = Instant::now();
+        for (attempt, expected) in [1, 2, 4, 8, 16, 30, 30].into_iter().enumerate() {
+            scheduler.record_failure_at(chunk, start);
+            let backoff = Duration::from_secs(expected);
+            assert!(!scheduler.retry_due_at(chunk, start + backoff - Duration::from_millis(1)), "{}", attempt);
+            assert!(scheduler.retry_due_at(chunk, start + backoff), "{}", attempt);
+        }
+        assert!(scheduler.on_received(chunk));
+        assert!(scheduler.retry_due_at(chunk, start));
+    }
+
+    #[test]
+    fn chunks_are_lost_only_without_holders_after_every_attempt() {
+        let chunk = &hashes(1)[0];
+        let peer = PeerId::random();
+        let mut scheduler = scheduler(std::slice::from_ref(chunk), &[peer]);
+        for _ in 0..MAX_FETCH_ATTEMPTS {
+            scheduler.record_failure(chunk);
+        }
+        assert!(!scheduler.is_lost(chunk));
+
+        let mut scheduler = DownloadScheduler::default();
+        for _ in 1..MAX_FETCH_ATTEMPTS {
+            scheduler.record_failure(chunk);
+        }
+        assert!(!scheduler.is_lost(chunk));
+        scheduler.record_failure(chunk);
+        assert!(scheduler.is_lost(chunk));
+    }
+
+    #[test]
+    fn forgetting_a_holder_frees_its_fetch() {
+        let chunks = hashes(1);
+        let [first, second] = [PeerId::random(), PeerId::random()];
+        let mut scheduler = scheduler(&chunks, &[first, second]);
+        let scores = HashMap::from([(first, 5.0)]);
+        assert_eq!(scheduler.assign(&chunks, &scores)[0].1, first);
+        assert_eq!(scheduler.progress(&chunks).in_flight, 1);
+
+        assert!(scheduler.forget_holder(&chunks[0], &first));
+        assert_eq!(scheduler.progress(&chunks).in_flight, 0);
+        assert_eq!(scheduler.assign(&chunks, &scores)[0].1, second);
+        assert!(!scheduler.forget_holder(&chunks[0], &second));
+        assert!(!scheduler.has_holders(&chunks[0]));
+    }
+}
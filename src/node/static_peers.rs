@@ -0,0 +1,139 @@
+use crate::node::MyBehaviour;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use std::time::{Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// A peer dialed at a known address, from `--bootstrap` or the config file.
+struct StaticPeer {
+    address: Multiaddr,
+    /// Taken from the `/p2p/` suffix of the address or learned on first connect.
+    peer_id: Option<PeerId>,
+    dial: Option<ConnectionId>, // dial in flight
+    connected: bool,
+    backoff: Duration,
+    next_dial: Option<Instant>,
+}
+
+/// Keeps the connections to statically configured peers up, redialing a
+/// dropped or unreachable peer with exponential backoff.
+pub struct StaticPeers {
+    peers: Vec<StaticPeer>,
+}
+
+impl StaticPeers {
+    pub fn new(addresses: Vec<Multiaddr>) -> Self {
+        let now = Instant::now();
+        let peers = addresses
+            .into_iter()
+            .map(|address| StaticPeer {
+                peer_id: peer_id_of(&address),
+                address,
+                dial: None,
+                connected: false,
+                backoff: INITIAL_BACKOFF,
+                next_dial: Some(now),
+            })
+            .collect();
+        StaticPeers { peers }
+    }
+
+    /// Dials every peer whose redial is due.
+    pub fn dial_due(&mut self, swarm: &mut Swarm<MyBehaviour>) {
+        let now = Instant::now();
+        for peer in &mut self.peers {
+            if peer.connected || peer.dial.is_some() || peer.next_dial.is_none_or(|at| at > now) {
+                continue;
+            }
+            if let Some(peer_id) = peer.peer_id {
+                if swarm.is_connected(&peer_id) {
+                    peer.connected = true;
+                    continue;
+                }
+            }
+            let opts = DialOpts::unknown_peer_id()
+                .address(peer.address.clone())
+                .build();
+            let connection_id = opts.connection_id();
+            println!("Dialing static peer {}", peer.address);
+            match swarm.dial(opts) {
+                Ok(()) => peer.dial = Some(connection_id),
+                Err(e) => {
+                    println!("Failed to dial {}: {}", peer.address, e);
+                    peer.schedule_redial();
+                }
+            }
+        }
+    }
+
+    /// Records a new connection. Returns the address the peer was dialed at
+    /// if it is a static peer, so it can be added to the DHT.
+    pub fn on_connected(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+    ) -> Option<Multiaddr> {
+        let peer = self.peers.iter_mut().find(|peer| {
+            peer.dial == Some(connection_id) || peer.peer_id == Some(peer_id)
+        })?;
+        peer.peer_id = Some(peer_id);
+        peer.dial = None;
+        peer.connected = true;
+        peer.backoff = INITIAL_BACKOFF;
+        peer.next_dial = None;
+        Some(peer.address.clone())
+    }
+
+    pub fn on_dial_failed(&mut self, connection_id: ConnectionId) {
+        if let Some(peer) = self
+            .peers
+            .iter_mut()
+            .find(|peer| peer.dial == Some(connection_id))
+        {
+            peer.dial = None;
+            let delay = peer.schedule_redial();
+            println!(
+                "Could not reach static peer {}; retrying in {}s",
+                peer.address,
+                delay.as_secs()
+            );
+        }
+    }
+
+    /// Schedules a redial once the last connection to a static peer closes.
+    pub fn on_disconnected(&mut self, peer_id: PeerId) {
+        for peer in &mut self.peers {
+            if peer.peer_id == Some(peer_id) && peer.connected {
+                peer.connected = false;
+                peer.next_dial = Some(Instant::now() + peer.backoff);
+                println!(
+                    "Lost static peer {}; redialing in {}s",
+                    peer.address,
+                    peer.backoff.as_secs()
+                );
+            }
+        }
+    }
+}
+
+impl StaticPeer {
+    /// Schedules the next dial and doubles the backoff; returns the delay.
+    fn schedule_redial(&mut self) -> Duration {
+        let delay = self.backoff;
+        self.next_dial = Some(Instant::now() + delay);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+/// The peer id in the trailing `/p2p/` component of `address`, if any.
+pub fn peer_id_of(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
@@ -1,17 +1,213 @@
-use crate::node::chunker::ChunkMetadata;
-use std::fs::File;
-use std::io::{self, Write};
-use std::path::Path;
-
-/// Save a chunk to a specified directory with a custom chunk filename.
-pub fn save_chunk(
-    chunk_data: &[u8],
-    _chunk_metadata: &ChunkMetadata,
-    output_dir: &str,
-    chunk_filename: &str,
-) -> io::Result<()> {
-    let chunk_path = Path::new(output_dir).join(chunk_filename);
-    let mut file = File::create(chunk_path)?;
-    file.write_all(chunk_data)?;
-    Ok(())
-}
\ No newline at end of file
+use crate::node::chunk_store::ChunkStore;
+use crate::node::manifest::{self, FileManifest, ShardBitmap};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const TRANSFER_LOG_FILE: &str = "transfers.jsonl";
+const IDENTITY_KEY_FILE: &str = "identity.key";
+const REPLICATION_FILE: &str = "replication.json";
+const DOWNLOAD_JOURNAL_FILE: &str = "downloads.json";
+
+/// On-disk layout of a node's data directory:
+///
+/// ```text
+/// <data-dir>/
+///   chunks/     encrypted chunks, named by their digest
+///   manifests/  signed file manifests
+///   downloads/  reassembled downloads
+///   keys/       the node identity key
+///   logs/       persisted file transfer logs
+///   replication.json  which peers hold the chunks of uploaded files
+///   downloads.json    downloads to resume after a restart
+/// ```
+#[derive(Debug, Clone)]
+pub struct StorageLayout {
+    root: PathBuf,
+}
+
+impl StorageLayout {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StorageLayout { root: root.into() }
+    }
+
+    /// Creates every directory of the layout that does not exist yet.
+    pub fn create_dirs(&self) -> io::Result<()> {
+        for dir in [
+            self.chunks_dir(),
+            self.manifests_dir(),
+            self.downloads_dir(),
+            self.keys_dir(),
+            self.logs_dir(),
+        ] {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn chunks_dir(&self) -> PathBuf {
+        self.root.join("chunks")
+    }
+
+    pub fn manifests_dir(&self) -> PathBuf {
+        self.root.join("manifests")
+    }
+
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.root.join("downloads")
+    }
+
+    pub fn keys_dir(&self) -> PathBuf {
+        self.root.join("keys")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+
+    pub fn identity_key_path(&self) -> PathBuf {
+        self.keys_dir().join(IDENTITY_KEY_FILE)
+    }
+
+    pub fn replication_path(&self) -> PathBuf {
+        self.root.join(REPLICATION_FILE)
+    }
+
+    pub fn download_journal_path(&self) -> PathBuf {
+        self.root.join(DOWNLOAD_JOURNAL_FILE)
+    }
+
+    /// Where the manifest of `file_name` is stored. Names that are not a
+    /// single path component are rejected, as they may come from a peer.
+    pub fn manifest_path(&self, file_name: &str) -> io::Result<PathBuf> {
+        if !manifest::is_plain_file_name(file_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file name: {:?}", file_name),
+            ));
+        }
+        Ok(self
+            .manifests_dir()
+            .join(format!("{}.manifest.json", file_name)))
+    }
+
+    pub fn download_path(&self, file_name: &str) -> PathBuf {
+        self.downloads_dir().join(file_name)
+    }
+
+    fn transfer_log_path(&self) -> PathBuf {
+        self.logs_dir().join(TRANSFER_LOG_FILE)
+    }
+}
+
+/// Store the manifest of a file, replacing any previous version.
+pub fn save_manifest(layout: &StorageLayout, manifest: &FileManifest) -> io::Result<()> {
+    write_atomic(
+        &layout.manifest_path(&manifest.file_name)?,
+        &serde_json::to_vec(manifest)?,
+    )
+}
+
+/// Replaces the file at `path` with `bytes` through a temporary file, so a
+/// crash leaves either the old or the new contents, never a truncated file.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Load the stored manifest of `file_name`, if any.
+pub fn load_manifest(layout: &StorageLayout, file_name: &str) -> Option<FileManifest> {
+    let bytes = fs::read(layout.manifest_path(file_name).ok()?).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Load every stored manifest, skipping files that cannot be parsed.
+pub fn list_manifests(layout: &StorageLayout) -> io::Result<Vec<FileManifest>> {
+    let mut manifests = Vec::new();
+    for entry in fs::read_dir(layout.manifests_dir())? {
+        let path = entry?.path();
+        if !path.to_string_lossy().ends_with(".manifest.json") {
+            continue;
+        }
+        if let Ok(manifest) = serde_json::from_slice(&fs::read(&path)?) {
+            manifests.push(manifest);
+        }
+    }
+    Ok(manifests)
+}
+
+/// Look up the chunks of `file_name` held locally.
+/// Returns the file's manifest and the digests of every chunk and parity
+/// shard listed in it that `store` holds, in manifest order.
+pub fn find_chunks_for_file(
+    layout: &StorageLayout,
+    store: &dyn ChunkStore,
+    file_name: &str,
+) -> Option<(FileManifest, ShardBitmap)> {
+    let manifest = load_manifest(layout, file_name)?;
+    let held = ShardBitmap::new(&manifest, |hash| store.has(hash));
+    if held.is_empty() {
+        None
+    } else {
+        Some((manifest, held))
+    }
+}
+
+/// Append one entry to the transfer log as a line of JSON.
+pub fn append_transfer_log<T: Serialize>(layout: &StorageLayout, entry: &T) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(layout.transfer_log_path())?;
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+/// Load every transfer log entry persisted so far, skipping unreadable lines.
+pub fn load_transfer_logs<T: DeserializeOwned>(layout: &StorageLayout) -> io::Result<Vec<T>> {
+    let file = match File::open(layout.transfer_log_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_paths_stay_in_the_manifests_dir() {
+        let layout = StorageLayout::new("/data");
+        assert_eq!(
+            layout.manifest_path("notes.txt").unwrap(),
+            Path::new("/data/manifests/notes.txt.manifest.json")
+        );
+        for name in ["../../x", "a/b", "..", ""] {
+            assert_eq!(
+                layout.manifest_path(name).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+            assert!(load_manifest(&layout, name).is_none());
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::node::chunk_store::ChunkStore;
+use crate::node::chunker::{self, ChunkMetadata, Chunker};
+use crate::node::command::{Chunking, ErasureCoding};
+use crate::node::crypto::{self, TopicKeys};
+use crate::node::erasure;
+use crate::node::error::NodeError;
+use crate::node::manifest::ErasureLayout;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Pipeline events waiting for the node; a pipeline that gets this far
+/// ahead of the network stops reading until the node catches up.
+pub const PIPELINE_QUEUE: usize = 16;
+/// Store requests in flight at once, for uploads and re-replication together.
+pub const MAX_STORES_IN_FLIGHT: usize = 32;
+/// Chunks waiting for a free store request before the node stops taking
+/// pipeline events.
+pub const MAX_QUEUED_STORES: usize = 64;
+
+/// What an upload pipeline reports to the node. Chunks and parity shards
+/// are in the chunk store by the time they are reported, so only their
+/// digests travel.
+pub enum PipelineEvent {
+    /// A chunk of a replicated file is ready to send.
+    Chunk {
+        job: u64,
+        file_name: String,
+        index: usize,
+        hash: String,
+    },
+    /// Every chunk and parity shard of a stripe of an erasure-coded file is
+    /// ready to send.
+    Stripe {
+        job: u64,
+        file_name: String,
+        stripe: usize,
+        shards: Vec<String>,
+    },
+    /// A file was read to the end, or could not be.
+    Finished {
+        job: u64,
+        file_name: String,
+        result: Result<ChunkedFile, NodeError>,
+    },
+}
+
+/// The chunks of a file the pipeline stored, in file order.
+pub struct ChunkedFile {
+    pub chunks: Vec<ChunkMetadata>,
+    pub erasure: Option<ErasureLayout>,
+}
+
+/// Uploads `paths` one after another in a background task. Every file is
+/// split a chunk at a time as `chunking` asks; each chunk is encrypted,
+/// hashed and put in `store` before the node is told to send it, so at most
+/// one stripe of a file is held in memory whatever its size.
+pub fn spawn(
+    job: u64,
+    paths: Vec<PathBuf>,
+    chunking: Chunking,
+    coding: Option<ErasureCoding>,
+    keys: TopicKeys,
+    store: Arc<dyn ChunkStore>,
+    events: mpsc::Sender<PipelineEvent>,
+) {
+    tokio::spawn(async move {
+        for path in paths {
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("unknown")
+                .to_string();
+            let pipeline = Pipeline {
+                job,
+                file_name,
+                chunking,
+                coding,
+                keys: &keys,
+                store: store.as_ref(),
+                events: &events,
+            };
+            let result = pipeline.run(&path).await;
+            let file_name = match &result {
+                Ok(_) => pipeline.file_name,
+                Err(_) => path.display().to_string(),
+            };
+            let finished = PipelineEvent::Finished {
+                job,
+                file_name,
+                result,
+            };
+            if events.send(finished).await.is_err() {
+                return; // the node stopped
+            }
+        }
+    });
+}
+
+struct Pipeline<'a> {
+    job: u64,
+    file_name: String,
+    chunking: Chunking,
+    coding: Option<ErasureCoding>,
+    keys: &'a TopicKeys,
+    store: &'a dyn ChunkStore,
+    events: &'a mpsc::Sender<PipelineEvent>,
+}
+
+impl Pipeline<'_> {
+    async fn run(&self, path: &Path) -> Result<ChunkedFile, NodeError> {
+        let file = tokio::fs::File::open(path).await?;
+        let file_len = file.metadata().await?.len();
+        let mut chunker = Chunker::new(file, self.chunking);
+        // Every shard is padded to the largest chunk the file can have.
+        let shard_size = crypto::encrypted_len(
+            usize::try_from(file_len)
+                .unwrap_or(usize::MAX)
+                .min(chunker.max_chunk_size()),
+        );
+
+        let mut chunks = Vec::new();
+        let mut parity_hashes = Vec::new();
+        let mut stripe = Vec::new();
+        while let Some(data) = chunker.next_chunk().await? {
+            // Chunks are addressed by the digest of their encrypted bytes,
+            // which is what replicas store and verify.
+            let encrypted = self.keys.encrypt_chunk(&data);
+            let hash = chunker::hash_chunk(&encrypted);
+            self.store.put(&hash, &encrypted)?;
+            chunks.push(ChunkMetadata {
+                size: data.len(),
+                hash: hash.clone(),
+            });
+
+            match self.coding {
+                None => {
+                    self.send(PipelineEvent::Chunk {
+                        job: self.job,
+                        file_name: self.file_name.clone(),
+                        index: chunks.len() - 1,
+                        hash,
+                    })
+                    .await?;
+                }
+                Some(coding) => {
+                    stripe.push((hash, encrypted));
+                    if stripe.len() == coding.data_shards {
+                        self.finish_stripe(&mut stripe, coding, shard_size, &mut parity_hashes)
+                            .await?;
+                    }
+                }
+            }
+        }
+
+        let erasure = match self.coding {
+            Some(coding) => {
+                if !stripe.is_empty() {
+                    self.finish_stripe(&mut stripe, coding, shard_size, &mut parity_hashes)
+                        .await?;
+                }
+                Some(ErasureLayout {
+                    data_shards: coding.data_shards,
+                    parity_shards: coding.parity_shards,
+                    shard_size,
+                    parity_hashes,
+                })
+            }
+            None => None,
+        };
+        Ok(ChunkedFile { chunks, erasure })
+    }
+
+    /// Computes and stores the parity shards of the encrypted chunks in
+    /// `stripe`, then reports the stripe.
+    async fn finish_stripe(
+        &self,
+        stripe: &mut Vec<(String, Vec<u8>)>,
+        coding: ErasureCoding,
+        shard_size: usize,
+        parity_hashes: &mut Vec<String>,
+    ) -> Result<(), NodeError> {
+        let (mut shards, data): (Vec<String>, Vec<Vec<u8>>) = stripe.drain(..).unzip();
+        let parity = erasure::encode_stripe(&data, shard_size, coding.data_shards, coding.parity_shards)
+            .map_err(|e| {
+                NodeError::Config(format!("cannot erasure code '{}': {}", self.file_name, e))
+            })?;
+        // Parity shards are encrypted with the topic key like chunks, so
+        // replicas accept and address them the same way.
+        for shard in parity {
+            let encrypted = self.keys.encrypt_chunk(&shard);
+            let hash = chunker::hash_chunk(&encrypted);
+            self.store.put(&hash, &encrypted)?;
+            parity_hashes.push(hash.clone());
+            shards.push(hash);
+        }
+        self.send(PipelineEvent::Stripe {
+            job: self.job,
+            file_name: self.file_name.clone(),
+            stripe: parity_hashes.len() / coding.parity_shards - 1,
+            shards,
+        })
+        .await
+    }
+
+    async fn send(&self, event: PipelineEvent) -> Result<(), NodeError> {
+        self.events.send(event).await.map_err(|_| NodeError::Stopped)
+    }
+}
@@ -0,0 +1,144 @@
+use crate::node::FileTransferLog;
+use bincode::Options;
+use std::fmt;
+
+/// Version of the gossip message format. Peers drop envelopes of any other version.
+pub const PROTOCOL_VERSION: u16 = 5;
+
+/// Upper bound on a decoded message, well above the gossipsub transmit limit.
+const MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+
+/// Everything published to the topic, sealed with the topic key.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum WireMessage {
+    /// Free-form chat text.
+    Chat { text: String },
+    /// Announces an upload. Peers fetch the manifest from the sender over
+    /// the file transfer protocol, as it may be far larger than a gossip
+    /// message can be.
+    Announce {
+        file_name: String,
+        merkle_root: String,
+        publisher: String,
+    },
+    /// A replica stored a chunk.
+    TransferLog(FileTransferLog),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Envelope {
+    version: u16,
+    message: WireMessage,
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    UnsupportedVersion(u16),
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported protocol version {} (expected {})",
+                version, PROTOCOL_VERSION
+            ),
+            WireError::Malformed(e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(MAX_MESSAGE_SIZE)
+}
+
+/// Encodes `message` in an envelope of the current protocol version.
+pub fn encode(message: WireMessage) -> Result<Vec<u8>, WireError> {
+    let envelope = Envelope {
+        version: PROTOCOL_VERSION,
+        message,
+    };
+    options().serialize(&envelope).map_err(WireError::Malformed)
+}
+
+/// Decodes an envelope, checking its version before the message itself.
+pub fn decode(data: &[u8]) -> Result<WireMessage, WireError> {
+    let version: u16 = options()
+        .allow_trailing_bytes()
+        .deserialize(data)
+        .map_err(WireError::Malformed)?;
+    if version != PROTOCOL_VERSION {
+        return Err(WireError::UnsupportedVersion(version));
+    }
+    let envelope: Envelope = options().deserialize(data).map_err(WireError::Malformed)?;
+    Ok(envelope.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Serialize)]
+    struct OtherEnvelope {
+        version: u16,
+        payload: Vec<u8>,
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let encoded = encode(WireMessage::Chat {
+            text: "hello".to_string(),
+        })
+        .unwrap();
+        match decode(&encoded).unwrap() {
+            WireMessage::Chat { text } => assert_eq!(text, "hello"),
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn announcements_stay_small() {
+        let encoded = encode(WireMessage::Announce {
+            file_name: "data.bin".to_string(),
+            merkle_root: "0".repeat(64),
+            publisher: "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".to_string(),
+        })
+        .unwrap();
+        assert!(encoded.len() < 256);
+        match decode(&encoded).unwrap() {
+            WireMessage::Announce { file_name, .. } => assert_eq!(file_name, "data.bin"),
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn other_versions_are_unsupported_not_malformed() {
+        // An envelope of another version need not parse as a current message.
+        for version in [PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            let envelope = OtherEnvelope {
+                version,
+                payload: vec![0xff; 16],
+            };
+            let encoded = options().serialize(&envelope).unwrap();
+            assert!(matches!(
+                decode(&encoded),
+                Err(WireError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn garbage_is_malformed() {
+        let mut encoded = encode(WireMessage::Chat {
+            text: "hello".to_string(),
+        })
+        .unwrap();
+        encoded.truncate(encoded.len() - 2);
+        assert!(matches!(decode(&encoded), Err(WireError::Malformed(_))));
+        assert!(matches!(decode(&[]), Err(WireError::Malformed(_))));
+    }
+}
@@ -0,0 +1,141 @@
+use p2p_sys::{DownloadPhase, LogQuery, NodeHandle};
+use tokio::io::{self, AsyncBufReadExt};
+
+/// Why the prompt stopped reading commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The user asked for the node to stop with `@shutdown`.
+    Shutdown,
+    /// Stdin closed, e.g. because the peer runs in the background.
+    StdinClosed,
+}
+
+/// Reads commands from stdin and runs them against `node` until stdin closes
+/// or the user types `@shutdown`.
+pub async fn run(node: NodeHandle) -> Exit {
+    let mut stdin = io::BufReader::new(io::stdin()).lines();
+    loop {
+        match stdin.next_line().await {
+            Ok(Some(input)) if input.trim() == "@shutdown" => return Exit::Shutdown,
+            Ok(Some(input)) => handle_user_input(&input, &node).await,
+            Ok(None) => return Exit::StdinClosed,
+            Err(e) => println!("Error reading stdin: {}", e),
+        }
+    }
+}
+
+async fn handle_user_input(input: &str, node: &NodeHandle) {
+    let trimmed = input.trim();
+    if trimmed == "@upload" {
+        #[cfg(feature = "gui")]
+        if let Some(file_path) = select_file() {
+            upload(node, file_path.to_string_lossy().into_owned());
+        } else {
+            println!("No file selected for upload.");
+        }
+        #[cfg(not(feature = "gui"))]
+        println!("Usage: @upload <path|directory|glob>");
+    } else if let Some(spec) = trimmed.strip_prefix("@upload ") {
+        upload(node, spec.trim().to_string());
+    } else if trimmed == "@check_scores" {
+        match node.scores().await {
+            Ok(scores) => {
+                println!("Current peer scores:");
+                for peer in scores {
+                    println!("Peer ID: {}, Score: {}", peer.peer_id, peer.score);
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    } else if trimmed == "@check_logs" {
+        match node.logs(LogQuery::default()).await {
+            Ok(logs) => {
+                for log in logs {
+                    println!("Log: {:?}", log);
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    } else if trimmed.starts_with("@download ") {
+        let parts: Vec<&str> = trimmed.split_whitespace().collect();
+        if parts.len() < 2 {
+            println!("Usage: @download <filename>");
+        } else {
+            download(node, parts[1].to_string());
+        }
+    } else if trimmed == "@downloads" {
+        match node.downloads().await {
+            Ok(downloads) if downloads.is_empty() => println!("No downloads."),
+            Ok(downloads) => {
+                for download in downloads {
+                    let total = match download.total_chunks {
+                        Some(total) => total.to_string(),
+                        None => "?".to_string(),
+                    };
+                    match &download.state {
+                        DownloadPhase::Downloading => println!(
+                            "{}: {}/{} chunks, {} in flight",
+                            download.file_name, download.received_chunks, total, download.in_flight
+                        ),
+                        DownloadPhase::Failed { reason, .. } => println!(
+                            "{}: {}/{} chunks, failed: {}",
+                            download.file_name, download.received_chunks, total, reason
+                        ),
+                    }
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    } else if let Some(file_name) = trimmed.strip_prefix("@downloads cancel ") {
+        if let Err(e) = node.cancel_download(file_name.trim()).await {
+            println!("{}", e);
+        }
+    } else if trimmed.starts_with("@replicate ") {
+        let parts: Vec<&str> = trimmed.split_whitespace().collect();
+        match parts.get(2).and_then(|n| n.parse::<usize>().ok()) {
+            Some(replicas) if parts.len() == 3 => {
+                if let Err(e) = node.set_replication_factor(parts[1], replicas).await {
+                    println!("{}", e);
+                }
+            }
+            _ => println!("Usage: @replicate <filename> <replicas>"),
+        }
+    } else if let Err(e) = node.publish(trimmed).await {
+        println!("Publish error: {}", e);
+    }
+}
+
+/// Uploads in the background so the prompt stays responsive while replicas answer.
+fn upload(node: &NodeHandle, spec: String) {
+    let node = node.clone();
+    tokio::spawn(async move {
+        match node.put(vec![spec]).await {
+            Ok(reports) => {
+                for report in reports {
+                    println!(
+                        "Upload of '{}' finished: {}/{} chunks replicated",
+                        report.file_name, report.replicated_chunks, report.total_chunks
+                    );
+                }
+            }
+            Err(e) => println!("Upload failed: {}", e),
+        }
+    });
+}
+
+/// Downloads in the background; the node reports progress as chunks arrive.
+fn download(node: &NodeHandle, file_name: String) {
+    let node = node.clone();
+    tokio::spawn(async move {
+        if let Err(e) = node.get_file(&file_name, None).await {
+            println!("Download of '{}' failed: {}", file_name, e);
+        }
+    });
+}
+
+/// Opens a file dialog for the user to select a file.
+/// Returns the path to the selected file.
+#[cfg(feature = "gui")]
+fn select_file() -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new().pick_file()
+}
@@ -0,0 +1,46 @@
+//! Peers that do not know the topic password are rejected and blacklisted.
+
+mod common;
+
+use common::any_port;
+use p2p_sys::NodeEvent;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::sleep;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peers_with_the_wrong_password_are_blacklisted() {
+    let node_dir = TempDir::new().unwrap();
+    let mut node =
+        common::start(common::builder("auth-test", node_dir.path()).listen_on(any_port()));
+    let node_addr = node.listen_addr().await;
+
+    let intruder_dir = TempDir::new().unwrap();
+    let intruder = common::start(
+        common::builder("auth-test", intruder_dir.path())
+            .secret("not the password")
+            .listen_on(any_port())
+            .bootstrap_peer(node_addr),
+    );
+    let intruder_id = intruder.peer_id();
+
+    let (rejected, reason) = node
+        .wait_for_event(|event| match event {
+            NodeEvent::PeerRejected { peer_id, reason } => Some((*peer_id, reason.clone())),
+            NodeEvent::PeerAuthenticated { peer_id } if *peer_id == intruder_id => {
+                panic!("the intruder was authenticated")
+            }
+            _ => None,
+        })
+        .await;
+    assert_eq!(rejected, intruder_id);
+    assert_eq!(reason, "invalid challenge response");
+
+    // The intruder stays out of the score table and cannot store chunks.
+    sleep(Duration::from_secs(1)).await;
+    let peers = node.handle.peers().await.unwrap();
+    assert!(peers
+        .iter()
+        .all(|peer| peer.peer_id != intruder_id.to_string() || peer.score.is_none()));
+    assert!(node.handle.scores().await.unwrap().is_empty());
+}
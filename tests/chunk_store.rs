@@ -0,0 +1,131 @@
+//! Nodes keeping their chunks in memory instead of the data directory.
+
+mod common;
+
+use common::{any_port, wait_for_peers};
+use p2p_sys::node::chunk_store::{ChunkStore, MemoryChunkStore};
+use p2p_sys::{ErasureCoding, Multiaddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::{sleep, timeout};
+
+struct TestNode {
+    node: common::TestNode,
+    store: Arc<MemoryChunkStore>,
+    data_dir: TempDir,
+}
+
+fn start(bootstrap: Option<Multiaddr>) -> TestNode {
+    let data_dir = TempDir::new().unwrap();
+    let store = Arc::new(MemoryChunkStore::default());
+    let mut builder = common::builder("chunk-store-test", data_dir.path())
+        .replication_factor(1)
+        .chunk_store(store.clone())
+        .listen_on(any_port());
+    if let Some(addr) = bootstrap {
+        builder = builder.bootstrap_peer(addr);
+    }
+    TestNode {
+        node: common::start(builder),
+        store,
+        data_dir,
+    }
+}
+
+/// An uploader and a replica connected to it.
+async fn start_pair() -> (TestNode, TestNode) {
+    let mut uploader = start(None);
+    let uploader_addr = uploader.node.listen_addr().await;
+    let replica = start(Some(uploader_addr));
+    wait_for_peers(&uploader.node.handle, &[replica.node.peer_id()]).await;
+    wait_for_peers(&replica.node.handle, &[uploader.node.peer_id()]).await;
+    (uploader, replica)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_round_trips_through_memory_stores() {
+    let (uploader, replica) = start_pair().await;
+
+    let files = TempDir::new().unwrap();
+    let path = files.path().join("memory.bin");
+    let data: Vec<u8> = (0..100_000).map(|i| (i * 7 % 253) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let report = uploader.node.handle.put_file(&path).await.unwrap();
+    assert!(report.is_complete(), "{:?}", report);
+    assert_eq!(replica.store.list().unwrap().len(), report.total_chunks);
+
+    let output = files.path().join("copy.bin");
+    let downloaded = timeout(
+        Duration::from_secs(60),
+        replica.node.handle.get_file("memory.bin", Some(output.clone())),
+    )
+    .await
+    .expect("download timed out")
+    .unwrap();
+    assert_eq!(downloaded, output);
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+
+    // Chunks never touch the data directory.
+    for node in [&uploader, &replica] {
+        let chunks_dir = node.data_dir.path().join("chunks");
+        let on_disk = std::fs::read_dir(&chunks_dir).map_or(0, |entries| entries.count());
+        assert_eq!(on_disk, 0, "{}", chunks_dir.display());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn erasure_coded_downloads_rebuild_lost_chunks() {
+    let (uploader, replica) = start_pair().await;
+
+    let files = TempDir::new().unwrap();
+    let path = files.path().join("coded.bin");
+    let data: Vec<u8> = (0..100_000).map(|i| (i * 11 % 251) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let coding = ErasureCoding {
+        data_shards: 2,
+        parity_shards: 1,
+    };
+    let reports = uploader
+        .node
+        .handle
+        .put_erasure_coded(vec![path.to_string_lossy().into_owned()], coding)
+        .await
+        .unwrap();
+    assert!(reports[0].is_complete(), "{:?}", reports);
+
+    // Both nodes hold every shard; drop one chunk of each of two stripes
+    // from both, so only parity can bring them back.
+    let manifest_path = replica
+        .data_dir
+        .path()
+        .join("manifests/coded.bin.manifest.json");
+    timeout(Duration::from_secs(30), async {
+        while !manifest_path.exists() {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("manifest did not arrive");
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+    let chunk_hashes = manifest["chunk_hashes"].as_array().unwrap();
+    let lost: Vec<&str> = [0, 3].iter().map(|i| chunk_hashes[*i].as_str().unwrap()).collect();
+    for hash in &lost {
+        replica.store.delete(hash).unwrap();
+        uploader.store.delete(hash).unwrap();
+    }
+
+    let output = files.path().join("copy.bin");
+    timeout(
+        Duration::from_secs(60),
+        replica.node.handle.get_file("coded.bin", Some(output.clone())),
+    )
+    .await
+    .expect("download timed out")
+    .unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+    for hash in lost {
+        assert!(replica.store.has(hash));
+    }
+}
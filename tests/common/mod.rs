@@ -0,0 +1,106 @@
+//! Fixtures shared by the integration tests.
+
+#![allow(dead_code)]
+
+use p2p_sys::{Multiaddr, Node, NodeBuilder, NodeError, NodeEvent, NodeHandle, PeerId};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+pub const SECRET: &str = "password";
+
+/// A node of `topic` keeping its data in `data_dir`, with mDNS off so tests
+/// running side by side do not find each other.
+pub fn builder(topic: &str, data_dir: &Path) -> NodeBuilder {
+    Node::builder()
+        .topic(topic)
+        .secret(SECRET)
+        .data_dir(data_dir)
+        .mdns(false)
+}
+
+pub fn addr(s: &str) -> Multiaddr {
+    s.parse().unwrap()
+}
+
+/// A loopback address on a port the system picks.
+pub fn any_port() -> Multiaddr {
+    addr("/ip4/127.0.0.1/tcp/0")
+}
+
+pub struct TestNode {
+    pub handle: NodeHandle,
+    pub task: JoinHandle<Result<(), NodeError>>,
+    events: broadcast::Receiver<NodeEvent>,
+}
+
+/// Builds `builder` and spawns the node.
+pub fn start(builder: NodeBuilder) -> TestNode {
+    let (node, handle) = builder.build().unwrap();
+    // Subscribed before the node runs, so no listen address is missed.
+    let events = handle.subscribe_events();
+    TestNode {
+        handle,
+        task: tokio::spawn(node.run()),
+        events,
+    }
+}
+
+impl TestNode {
+    pub fn peer_id(&self) -> PeerId {
+        self.handle.local_peer_id()
+    }
+
+    /// Waits for the first address the node listens on, and returns it with
+    /// the node's peer id so other nodes can dial it.
+    pub async fn listen_addr(&mut self) -> Multiaddr {
+        let address = self
+            .wait_for_event(|event| match event {
+                NodeEvent::Listening { address } => Some(address.clone()),
+                _ => None,
+            })
+            .await;
+        addr(&format!("{}/p2p/{}", address, self.peer_id()))
+    }
+
+    /// Waits for the first event `select` picks, and returns what it picked.
+    pub async fn wait_for_event<T>(&mut self, select: impl Fn(&NodeEvent) -> Option<T>) -> T {
+        timeout(Duration::from_secs(30), async {
+            loop {
+                match self.events.recv().await {
+                    Ok(event) => {
+                        if let Some(picked) = select(&event) {
+                            return picked;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => panic!("node stopped"),
+                }
+            }
+        })
+        .await
+        .expect("event did not arrive")
+    }
+}
+
+/// Waits until `node` has authenticated every peer in `expected`.
+pub async fn wait_for_peers(node: &NodeHandle, expected: &[PeerId]) {
+    let expected: Vec<String> = expected.iter().map(|peer| peer.to_string()).collect();
+    timeout(Duration::from_secs(60), async {
+        loop {
+            let peers = node.peers().await.unwrap();
+            if expected.iter().all(|id| {
+                peers
+                    .iter()
+                    .any(|peer| &peer.peer_id == id && peer.connected && peer.score.is_some())
+            }) {
+                return;
+            }
+            sleep(Duration::from_millis(200)).await;
+        }
+    })
+    .await
+    .expect("peers did not connect");
+}
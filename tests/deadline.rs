@@ -0,0 +1,87 @@
+//! A download that cannot finish fails at its deadline, naming the chunks
+//! that never arrived.
+
+mod common;
+
+use common::{any_port, wait_for_peers, TestNode};
+use p2p_sys::{DownloadPhase, Multiaddr, NodeError};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::timeout;
+
+fn start(data_dir: &Path, bootstrap: Option<&Multiaddr>) -> TestNode {
+    let mut builder = common::builder("deadline-test", data_dir)
+        .replication_factor(1)
+        .download_deadline(Duration::from_secs(3))
+        .listen_on(any_port());
+    if let Some(addr) = bootstrap {
+        builder = builder.bootstrap_peer(addr.clone());
+    }
+    common::start(builder)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn downloads_missing_chunks_fail_at_the_deadline() {
+    let uploader_dir = TempDir::new().unwrap();
+    let mut uploader = start(uploader_dir.path(), None);
+    let uploader_addr = uploader.listen_addr().await;
+    let holder_dir = TempDir::new().unwrap();
+    let holder = start(holder_dir.path(), Some(&uploader_addr));
+    wait_for_peers(&uploader.handle, &[holder.peer_id()]).await;
+
+    let files = TempDir::new().unwrap();
+    let path = files.path().join("partial.bin");
+    let data: Vec<u8> = (0..100_000).map(|i| (i * 17 % 241) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let report = uploader.handle.put_file(&path).await.unwrap();
+    assert!(report.is_complete(), "{:?}", report);
+
+    // Nobody keeps chunks 1 and 3 any more.
+    let manifest: serde_json::Value = serde_json::from_slice(
+        &std::fs::read(uploader_dir.path().join("manifests/partial.bin.manifest.json")).unwrap(),
+    )
+    .unwrap();
+    for index in [1, 3] {
+        let hash = manifest["chunk_hashes"][index].as_str().unwrap();
+        for dir in [&uploader_dir, &holder_dir] {
+            let chunk = dir.path().join("chunks").join(hash);
+            if chunk.exists() {
+                std::fs::remove_file(chunk).unwrap();
+            }
+        }
+    }
+
+    let downloader_dir = TempDir::new().unwrap();
+    let downloader = start(downloader_dir.path(), Some(&uploader_addr));
+    wait_for_peers(&downloader.handle, &[uploader.peer_id()]).await;
+    let result = timeout(
+        Duration::from_secs(30),
+        downloader.handle.get_file("partial.bin", None),
+    )
+    .await
+    .expect("download did not end");
+    match result {
+        Err(NodeError::DownloadFailed {
+            reason,
+            missing_chunks,
+        }) => {
+            assert_eq!(reason, "the download deadline passed");
+            assert_eq!(missing_chunks, vec![1, 3]);
+        }
+        other => panic!("download ended with {:?}", other),
+    }
+
+    let downloads = downloader.handle.downloads().await.unwrap();
+    let failed = downloads
+        .iter()
+        .find(|info| info.file_name == "partial.bin")
+        .expect("failed download is not listed");
+    assert_eq!(
+        failed.state,
+        DownloadPhase::Failed {
+            reason: "the download deadline passed".to_string(),
+            missing_chunks: vec![1, 3],
+        }
+    );
+}
@@ -0,0 +1,107 @@
+//! Nodes on loopback where one only listens through a relay, standing in for
+//! a peer behind NAT that cannot accept direct connections.
+
+mod common;
+
+use common::{addr, any_port, wait_for_peers, TestNode};
+use p2p_sys::{NodeBuilder, NodeEvent};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::timeout;
+
+struct RelayNode {
+    node: TestNode,
+    _data_dir: TempDir,
+}
+
+fn start(configure: impl FnOnce(NodeBuilder) -> NodeBuilder) -> RelayNode {
+    let data_dir = TempDir::new().unwrap();
+    RelayNode {
+        node: common::start(configure(common::builder("relay-test", data_dir.path()))),
+        _data_dir: data_dir,
+    }
+}
+
+/// A relay, a public node and a node reachable only through the relay,
+/// with the public node dialing the other through its circuit address.
+async fn start_network() -> (RelayNode, RelayNode, RelayNode) {
+    let mut relay = start(|builder| builder.relay_server(true).listen_on(any_port()));
+    let relay_addr = relay.node.listen_addr().await;
+
+    let behind_nat = start(|builder| {
+        builder
+            .listen_on(addr(&format!("{}/p2p-circuit", relay_addr)))
+            .bootstrap_peer(relay_addr.clone())
+    });
+    let public = start(|builder| {
+        builder
+            .listen_on(any_port())
+            .bootstrap_peer(relay_addr.clone())
+            .bootstrap_peer(addr(&format!(
+                "{}/p2p-circuit/p2p/{}",
+                relay_addr,
+                behind_nat.node.peer_id()
+            )))
+    });
+
+    wait_for_peers(
+        &public.node.handle,
+        &[relay.node.peer_id(), behind_nat.node.peer_id()],
+    )
+    .await;
+    wait_for_peers(
+        &behind_nat.node.handle,
+        &[relay.node.peer_id(), public.node.peer_id()],
+    )
+    .await;
+    (relay, public, behind_nat)
+}
+
+fn write_file(dir: &TempDir, name: &str, len: usize) -> std::path::PathBuf {
+    let path = dir.path().join(name);
+    let data: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_behind_relay_stores_replicas() {
+    let (_relay, public, behind_nat) = start_network().await;
+    let mut events = behind_nat.node.handle.subscribe_events();
+
+    let files = TempDir::new().unwrap();
+    let report = public
+        .node
+        .handle
+        .put_file(write_file(&files, "shared.bin", 50_000))
+        .await
+        .unwrap();
+    assert!(report.is_complete(), "{:?}", report);
+
+    let uploader = public.node.handle.local_peer_id();
+    timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(NodeEvent::ChunkStored { peer_id, .. }) = events.recv().await {
+                if peer_id == uploader {
+                    return;
+                }
+            }
+        }
+    })
+    .await
+    .expect("no chunk reached the peer behind the relay");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_behind_relay_uploads() {
+    let (_relay, _public, behind_nat) = start_network().await;
+
+    let files = TempDir::new().unwrap();
+    let report = behind_nat
+        .node
+        .handle
+        .put_file(write_file(&files, "outbound.bin", 50_000))
+        .await
+        .unwrap();
+    assert!(report.is_complete(), "{:?}", report);
+}
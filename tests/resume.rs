@@ -0,0 +1,105 @@
+//! A node restarted in the middle of a download resumes it from its journal.
+
+mod common;
+
+use common::{any_port, wait_for_peers, TestNode};
+use p2p_sys::{Multiaddr, NodeEvent};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::timeout;
+
+fn start(data_dir: &Path, bootstrap: Option<&Multiaddr>) -> TestNode {
+    let mut builder = common::builder("resume-test", data_dir)
+        .replication_factor(1)
+        .listen_on(any_port());
+    if let Some(addr) = bootstrap {
+        builder = builder.bootstrap_peer(addr.clone());
+    }
+    common::start(builder)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restarted_download_fetches_only_missing_chunks() {
+    let uploader_dir = TempDir::new().unwrap();
+    let mut uploader = start(uploader_dir.path(), None);
+    let uploader_addr = uploader.listen_addr().await;
+    let holder_dir = TempDir::new().unwrap();
+    let holder = start(holder_dir.path(), Some(&uploader_addr));
+    wait_for_peers(&uploader.handle, &[holder.peer_id()]).await;
+
+    let files = TempDir::new().unwrap();
+    let path = files.path().join("resume.bin");
+    let data: Vec<u8> = (0..120_000).map(|i| (i * 13 % 247) as u8).collect();
+    std::fs::write(&path, &data).unwrap();
+    let report = uploader.handle.put_file(&path).await.unwrap();
+    assert!(report.is_complete(), "{:?}", report);
+
+    // Download the whole file once, then stop the node.
+    let downloader_dir = TempDir::new().unwrap();
+    let layout = downloader_dir.path();
+    let downloader = start(layout, Some(&uploader_addr));
+    wait_for_peers(&downloader.handle, &[uploader.peer_id()]).await;
+    timeout(Duration::from_secs(60), downloader.handle.get_file("resume.bin", None))
+        .await
+        .expect("download timed out")
+        .unwrap();
+    let TestNode { handle, task, .. } = downloader;
+    drop(handle);
+    timeout(Duration::from_secs(10), task)
+        .await
+        .expect("node did not stop")
+        .unwrap()
+        .unwrap();
+
+    // Leave it as a crash midway would: some chunks missing, no output
+    // file and the download still in the journal.
+    let mut chunks: Vec<_> = std::fs::read_dir(layout.join("chunks"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    chunks.sort();
+    assert_eq!(chunks.len(), report.total_chunks);
+    let missing = [&chunks[1], &chunks[4], &chunks[5]];
+    for chunk in missing {
+        std::fs::remove_file(chunk).unwrap();
+    }
+    std::fs::remove_file(layout.join("downloads").join("resume.bin")).unwrap();
+    std::fs::write(
+        layout.join("downloads.json"),
+        r#"{"resume.bin":{"started_at":"2026-01-01T00:00:00Z"}}"#,
+    )
+    .unwrap();
+
+    let downloader = start(layout, Some(&uploader_addr));
+    let mut events = downloader.handle.subscribe_events();
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(60), async {
+        loop {
+            match events.recv().await.unwrap() {
+                NodeEvent::DownloadProgress {
+                    file_name,
+                    received_chunks,
+                    total_chunks,
+                } if file_name == "resume.bin" => {
+                    assert_eq!(total_chunks, report.total_chunks);
+                    received.push(received_chunks);
+                }
+                NodeEvent::DownloadCompleted { file_name, .. } if file_name == "resume.bin" => {
+                    return;
+                }
+                _ => {}
+            }
+        }
+    })
+    .await
+    .expect("resumed download did not complete");
+
+    // Only the three deleted chunks were fetched.
+    let total = report.total_chunks;
+    assert_eq!(received, vec![total - 2, total - 1, total]);
+    for chunk in missing {
+        assert!(chunk.exists(), "{} was not fetched again", chunk.display());
+    }
+    assert_eq!(std::fs::read(layout.join("downloads").join("resume.bin")).unwrap(), data);
+}